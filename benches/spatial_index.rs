@@ -0,0 +1,126 @@
+//! Compares the boids broadphases on a flock spread evenly over the world,
+//! every boid querying the same neighbour radius as the flocking rules do.
+//!
+//! Run with `cargo bench --bench spatial_index`.
+
+use bevy::prelude::Vec2;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Checked with `cfg(test)` but without the test harness, the module's tests are
+// dropped and leave their imports and helpers unused, and the benchmarks only
+// need part of the partitions
+#[allow(unused_imports, dead_code)]
+#[path = "../src/bin/boids/spatial_partition.rs"]
+mod spatial_partition;
+// Only the parts the partitions need are used here
+#[allow(dead_code)]
+#[path = "../src/bin/boids/vector.rs"]
+mod vector;
+
+use spatial_partition::{QuadTree, SpatialHashGrid, SpatialIndex, AABB};
+
+const NEIGHBOR_RADIUS: f32 = 100.0;
+const FLOCK_SIZES: [usize; 3] = [1_000, 10_000, 50_000];
+
+/// Half size of a world holding `count` boids at roughly the density of the
+/// windowed simulation.
+fn world_half_size(count: usize) -> f32 {
+    (count as f32).sqrt() * 20.0
+}
+
+fn flock(count: usize) -> Vec<Vec2> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let half_size = world_half_size(count);
+    (0..count)
+        .map(|_| {
+            Vec2::new(
+                rng.gen_range(-half_size..half_size),
+                rng.gen_range(-half_size..half_size),
+            )
+        })
+        .collect()
+}
+
+fn quadtree(count: usize) -> Box<dyn SpatialIndex<usize>> {
+    let half_size = world_half_size(count);
+    Box::new(QuadTree::with_config(
+        AABB::new(Vec2::ZERO, Vec2::splat(half_size)),
+        spatial_partition::DEFAULT_CAPACITY,
+        12,
+    ))
+}
+
+fn grid(_count: usize) -> Box<dyn SpatialIndex<usize>> {
+    Box::new(SpatialHashGrid::new(NEIGHBOR_RADIUS))
+}
+
+type IndexBuilder = fn(usize) -> Box<dyn SpatialIndex<usize>>;
+
+const INDICES: [(&str, IndexBuilder); 2] = [("quadtree", quadtree), ("grid", grid)];
+
+fn query_all(index: &dyn SpatialIndex<usize>, positions: &[Vec2]) -> usize {
+    positions
+        .iter()
+        .map(|position| index.query_radius(*position, NEIGHBOR_RADIUS).len())
+        .sum()
+}
+
+/// Building the index from scratch then running one query per boid.
+fn rebuild_and_query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rebuild_and_query");
+    group.sample_size(10);
+
+    for count in FLOCK_SIZES {
+        let positions = flock(count);
+        for (name, build) in INDICES {
+            group.bench_with_input(BenchmarkId::new(name, count), &positions, |b, positions| {
+                b.iter(|| {
+                    let mut index = build(count);
+                    for (id, position) in positions.iter().enumerate() {
+                        index.insert(*position, id);
+                    }
+                    query_all(index.as_ref(), positions)
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// Moving every boid a little, like a simulation tick, then running one query per boid.
+fn update_and_query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_and_query");
+    group.sample_size(10);
+
+    for count in FLOCK_SIZES {
+        let half_size = world_half_size(count);
+        for (name, build) in INDICES {
+            let mut positions = flock(count);
+            let mut index = build(count);
+            for (id, position) in positions.iter().enumerate() {
+                index.insert(*position, id);
+            }
+            let mut rng = StdRng::seed_from_u64(7);
+
+            group.bench_function(BenchmarkId::new(name, count), |b| {
+                b.iter(|| {
+                    for (id, position) in positions.iter_mut().enumerate() {
+                        let old = *position;
+                        let step = Vec2::new(rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0));
+                        *position = (old + step)
+                            .clamp(Vec2::splat(-half_size + 1.0), Vec2::splat(half_size - 1.0));
+                        index.update(id, old, *position);
+                    }
+                    query_all(index.as_ref(), &positions)
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, rebuild_and_query, update_and_query);
+criterion_main!(benches);
@@ -0,0 +1,297 @@
+//! Group statistics of the flock, to tell whether a change of parameters really
+//! makes it flock: how aligned it is, how tightly packed, and how many separate
+//! groups it splits into.
+//!
+//! They are measured every step and published as diagnostics, with
+//! `--metrics <path>` on the command line they are written to `path` as CSV.
+
+use super::*;
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufWriter, Write};
+
+pub const POLARIZATION: DiagnosticId =
+    DiagnosticId::from_u128(0x6f3c_2a1e_8b4d_4c7a_9e05_d1b2_c3a4_e5f6);
+pub const MEAN_NEAREST_DISTANCE: DiagnosticId =
+    DiagnosticId::from_u128(0x1d8e_5b7c_3f2a_4e91_a6b0_c4d3_e2f1_0a9b);
+pub const CLUSTERS: DiagnosticId =
+    DiagnosticId::from_u128(0x9a4f_7e2d_1c6b_4835_b0e9_f8a7_d6c5_b4a3);
+
+/// Steps kept by each diagnostic.
+const HISTORY_LENGTH: usize = 120;
+
+const HEADER: &str = "tick,count,polarization,mean_nearest_distance,clusters";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlockMetrics {
+    pub count: usize,
+    /// Length of the mean heading, 1 when all boids fly the same way and close
+    /// to 0 when they head anywhere.
+    pub polarization: f32,
+    /// Mean distance from a boid to the closest other one, across the world
+    /// edges when boids wrap around them.
+    pub mean_nearest_distance: f32,
+    /// Groups of boids linked by chains of neighbours.
+    pub clusters: usize,
+}
+
+impl FlockMetrics {
+    /// Measures the flock `boids`, given as `(id, position, velocity)` and
+    /// indexed by `index`. Neighbours are looked for across the world edges
+    /// when boids wrap around them, boids closer than `link_radius` are in the
+    /// same cluster.
+    pub fn measure<T: Copy + Eq + Hash, V: Vector>(
+        boids: &[(T, V, V)],
+        index: &dyn SpatialIndex<T, V>,
+        world_bounds: &WorldBounds<V>,
+        link_radius: f32,
+    ) -> Self {
+        Self {
+            count: boids.len(),
+            polarization: polarization(boids.iter().map(|(_, _, velocity)| *velocity)),
+            mean_nearest_distance: mean_nearest_distance(boids, |position| {
+                world_bounds.k_nearest(index, position, 2)
+            }),
+            clusters: clusters(boids, |position| {
+                world_bounds.query_radius(index, position, link_radius)
+            }),
+        }
+    }
+}
+
+pub fn polarization<V: Vector>(velocities: impl Iterator<Item = V>) -> f32 {
+    let (heading, count) = velocities.fold((V::ZERO, 0), |(heading, count), velocity| {
+        (heading + velocity.normalize_or_zero(), count + 1)
+    });
+
+    if count == 0 {
+        0.0
+    } else {
+        heading.length() / count as f32
+    }
+}
+
+/// Mean distance to the closest boid `nearest` finds apart from the boid
+/// itself, zero when no boid has another one to be close to.
+fn mean_nearest_distance<T: Copy + Eq, V: Vector>(
+    boids: &[(T, V, V)],
+    nearest: impl Fn(V) -> Vec<(V, T)>,
+) -> f32 {
+    let distances = boids
+        .iter()
+        .filter_map(|(id, position, _)| {
+            nearest(*position)
+                .into_iter()
+                .find(|(_, other)| other != id)
+                .map(|(other, _)| other.distance_squared(*position).sqrt())
+        })
+        .collect::<Vec<_>>();
+
+    if distances.is_empty() {
+        0.0
+    } else {
+        distances.iter().sum::<f32>() / distances.len() as f32
+    }
+}
+
+/// Connected components of the graph linking every boid to the ones
+/// `neighbors` finds around it.
+fn clusters<T: Copy + Eq + Hash, V: Vector>(
+    boids: &[(T, V, V)],
+    neighbors: impl Fn(V) -> Vec<(V, T)>,
+) -> usize {
+    let indices = boids
+        .iter()
+        .enumerate()
+        .map(|(i, (id, _, _))| (*id, i))
+        .collect::<HashMap<_, _>>();
+
+    // Union-find, every boid starts as its own cluster
+    let mut parents = (0..boids.len()).collect::<Vec<_>>();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let mut clusters = boids.len();
+    for (i, (_, position, _)) in boids.iter().enumerate() {
+        for (_, other) in neighbors(*position) {
+            // Boids outside the flock being measured don't link anything
+            let j = match indices.get(&other) {
+                Some(j) => *j,
+                None => continue,
+            };
+            let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+            if a != b {
+                parents[a] = b;
+                clusters -= 1;
+            }
+        }
+    }
+
+    clusters
+}
+
+/// Metrics of every step written as CSV.
+#[derive(Resource)]
+pub struct MetricsLog {
+    out: BufWriter<File>,
+    tick: usize,
+}
+
+impl MetricsLog {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", HEADER)?;
+        Ok(Self { out, tick: 0 })
+    }
+
+    fn write(&mut self, metrics: &FlockMetrics) -> io::Result<()> {
+        writeln!(
+            self.out,
+            "{},{},{},{},{}",
+            self.tick,
+            metrics.count,
+            metrics.polarization,
+            metrics.mean_nearest_distance,
+            metrics.clusters
+        )?;
+        self.out.flush()
+    }
+}
+
+pub fn setup_diagnostics_system(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(
+        POLARIZATION,
+        "polarization",
+        HISTORY_LENGTH,
+    ));
+    diagnostics.add(Diagnostic::new(
+        MEAN_NEAREST_DISTANCE,
+        "mean_nearest_distance",
+        HISTORY_LENGTH,
+    ));
+    diagnostics.add(Diagnostic::new(CLUSTERS, "clusters", HISTORY_LENGTH));
+}
+
+/// Measures the flock once it moved, as long as the metrics are logged or the
+/// debug overlay shows them. Measuring costs about as much as a step.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn analysis_system<V: Vector>(
+    boids_partition: Res<BoidsPartition<V>>,
+    world_bounds: Res<WorldBounds<V>>,
+    config: Res<BoidsConfig>,
+    boids: Query<(Entity, &Position<V>, &Velocity<V>), With<Boid>>,
+    mut diagnostics: ResMut<Diagnostics>,
+    log: Option<ResMut<MetricsLog>>,
+    overlay: Option<Res<overlay::DebugOverlay>>,
+) {
+    if log.is_none() && !matches!(overlay, Some(overlay) if overlay.enabled) {
+        return;
+    }
+
+    let boids = boids
+        .iter()
+        .map(|(entity, position, velocity)| (entity, position.0, velocity.0))
+        .collect::<Vec<_>>();
+    let metrics = FlockMetrics::measure(
+        &boids,
+        boids_partition.0.as_ref(),
+        &world_bounds,
+        config.neighbor_radius(),
+    );
+
+    diagnostics.add_measurement(POLARIZATION, || metrics.polarization as f64);
+    diagnostics.add_measurement(MEAN_NEAREST_DISTANCE, || {
+        metrics.mean_nearest_distance as f64
+    });
+    diagnostics.add_measurement(CLUSTERS, || metrics.clusters as f64);
+
+    if let Some(mut log) = log {
+        if let Err(err) = log.write(&metrics) {
+            error!("Failed to write the metrics of tick {}: {}", log.tick, err);
+        }
+        log.tick += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spatial_partition::QuadTree;
+
+    /// Boids numbered in order, all heading along `velocity`.
+    fn flock(positions: &[Vec2], velocity: Vec2) -> Vec<(usize, Vec2, Vec2)> {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(id, position)| (id, *position, velocity))
+            .collect()
+    }
+
+    fn measure(boids: &[(usize, Vec2, Vec2)], world_bounds: &WorldBounds) -> FlockMetrics {
+        let mut index = QuadTree::new(world_bounds.aabb);
+        for (id, position, _) in boids {
+            index.insert(*position, *id);
+        }
+        FlockMetrics::measure(boids, &index, world_bounds, 10.0)
+    }
+
+    #[test]
+    fn polarization_of_headings() {
+        let aligned = [Vec2::X, Vec2::X * 5.0, Vec2::new(3.0, 0.0)];
+        assert_eq!(polarization(aligned.into_iter()), 1.0);
+
+        let opposed = [Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y];
+        assert_eq!(polarization(opposed.into_iter()), 0.0);
+
+        let half = [Vec2::X, Vec2::Y];
+        assert!((polarization(half.into_iter()) - 0.5f32.sqrt()).abs() < 1e-6);
+
+        assert_eq!(polarization(std::iter::empty::<Vec2>()), 0.0);
+    }
+
+    #[test]
+    fn groups_and_spacing() {
+        let world_bounds = WorldBounds::new(AABB::new(Vec2::ZERO, Vec2::splat(100.0)), Edges::Wrap);
+        // A chain of three 8 apart and a pair 4 apart, far from each other
+        let boids = flock(
+            &[
+                Vec2::new(0.0, 0.0),
+                Vec2::new(8.0, 0.0),
+                Vec2::new(16.0, 0.0),
+                Vec2::new(-50.0, 50.0),
+                Vec2::new(-50.0, 54.0),
+            ],
+            Vec2::Y,
+        );
+
+        let metrics = measure(&boids, &world_bounds);
+        assert_eq!(metrics.count, 5);
+        assert_eq!(metrics.polarization, 1.0);
+        assert_eq!(metrics.clusters, 2);
+        assert!((metrics.mean_nearest_distance - (8.0 * 3.0 + 4.0 * 2.0) / 5.0).abs() < 1e-6);
+
+        assert_eq!(measure(&[], &world_bounds), FlockMetrics::default());
+    }
+
+    #[test]
+    fn neighbours_across_wrapped_edges() {
+        let aabb = AABB::new(Vec2::ZERO, Vec2::splat(100.0));
+        let boids = flock(&[Vec2::new(-98.0, 0.0), Vec2::new(97.0, 0.0)], Vec2::X);
+
+        let wrapped = measure(&boids, &WorldBounds::new(aabb, Edges::Wrap));
+        assert_eq!(wrapped.clusters, 1);
+
+        let walled = measure(&boids, &WorldBounds::new(aabb, Edges::Reflect));
+        assert_eq!(walled.clusters, 2);
+
+        assert!((wrapped.mean_nearest_distance - 5.0).abs() < 1e-4);
+        assert!((walled.mean_nearest_distance - 195.0).abs() < 1e-4);
+    }
+}
@@ -1,182 +1,281 @@
 use super::*;
-#[derive(Debug, Default)]
-pub struct Boids {
-    pub positions: Vec<Vec2>,
-    pub velocities: Vec<Vec2>,
-    pub accelerations: Vec<Vec2>,
+use rand::Rng;
+use serde::Deserialize;
+
+/// Shape boids steer around, centred on the entity's `Transform`. In 3D
+/// circles are spheres and rectangles boxes.
+#[derive(Debug, Clone, Copy, Component)]
+pub enum Obstacle<V: Vector = Vec2> {
+    Circle { radius: f32 },
+    Rect { half_extents: V },
 }
 
-impl Boids {
-    pub fn size(&self) -> usize {
-        self.positions.len()
+impl<V: Vector> Obstacle<V> {
+    /// Point of the obstacle centred at `center` closest to `point`, `point`
+    /// itself when it is inside.
+    pub fn closest_point(&self, center: V, point: V) -> V {
+        match *self {
+            Obstacle::Circle { radius } => {
+                let offset = point - center;
+                if offset.length() <= radius {
+                    point
+                } else {
+                    center + offset.normalize() * radius
+                }
+            }
+            Obstacle::Rect { half_extents } => {
+                point.clamp(center - half_extents, center + half_extents)
+            }
+        }
     }
+}
 
-    pub fn add(&mut self, position: Vec2) {
-        self.positions.push(position);
+/// Entity boids are drawn to by `SeekTargets`.
+#[derive(Debug, Component)]
+pub struct Target;
 
-        let x = rand::random::<f32>() * 2.0 - 1.0;
-        let y = rand::random::<f32>() * 2.0 - 1.0;
-        let vel = Vec2::new(x, y);
-        self.velocities.push(vel);
-        self.accelerations.push(Vec2::ZERO);
-    }
+/// Entity boids run from with `FleeThreats`.
+#[derive(Debug, Component)]
+pub struct Threat;
 
-    pub fn update(&mut self) {
-        let triple_iter = self
-            .velocities
-            .iter_mut()
-            .zip(self.accelerations.iter_mut())
-            .zip(self.positions.iter_mut())
-            .map(|((a, b), c)| (a, b, c));
+/// Everything outside the flock the boids react to during a step.
+#[derive(Debug, Default)]
+pub struct Surroundings<V: Vector = Vec2> {
+    pub obstacles: Vec<(V, Obstacle<V>)>,
+    pub targets: Vec<V>,
+    pub threats: Vec<V>,
+    /// Edges of the world when boids steer clear of them.
+    pub walls: Option<AABB<V>>,
+}
 
-        for (velocity, acceleration, position) in triple_iter {
-            *velocity += *acceleration;
+/// What happens to boids reaching the edge of the world.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Edges {
+    /// They come back in on the opposite side.
+    #[default]
+    Wrap,
+    /// They bounce off.
+    Reflect,
+    /// They turn away from the edges before reaching them, and bounce off when
+    /// they come in too fast to turn.
+    Steer,
+}
 
-            if velocity.length() > Boids::MAX_SPEED {
-                *velocity = velocity.normalize() * Boids::MAX_SPEED;
-            }
-            *position += *velocity;
-            *acceleration = Vec2::ZERO;
-        }
-    }
+/// How a new flock is scattered around the origin.
+pub trait Scatter: Vector {
+    /// A random position, and a random velocity of at most unit length on each
+    /// axis.
+    fn scatter(rng: &mut impl Rng) -> (Self, Self);
+}
 
-    const MAX_SPEED: f32 = 8.0;
-    const MAX_FORCE: f32 = 1.0;
-
-    fn separate(velocities: &[Vec2], positions: &[Vec2]) -> Vec<Vec2> {
-        let desired_seperation = 25.0;
-
-        let mut steers = Vec::new();
-
-        for i in 0..positions.len() {
-            let mut steer = Vec2::ZERO;
-            let mut count = 0;
-            for j in 0..positions.len() {
-                let dist = (positions[i] - positions[j]).length();
-                if dist > 0.0 && dist < desired_seperation {
-                    let diff = positions[i] - positions[j];
-                    let diff = diff.normalize();
-                    let diff = diff / dist;
-                    steer += diff;
-                    count += 1;
-                }
-            }
+impl Scatter for Vec2 {
+    fn scatter(rng: &mut impl Rng) -> (Self, Self) {
+        let d = rng.gen::<f32>();
+        let dd = rng.gen::<f32>();
+        let r = d * std::f32::consts::TAU;
 
-            if count > 0 {
-                steer /= count as f32;
-            }
+        let x = f32::sin(r) * 2.0 - 1.0;
+        let y = f32::cos(r) * 2.0 - 1.0;
 
-            if steer.length() > 0.0 {
-                steer = steer.normalize();
-                steer *= Boids::MAX_SPEED;
-                steer -= velocities[i];
-                if steer.length() > Boids::MAX_FORCE {
-                    steer = steer.normalize();
-                    steer *= Boids::MAX_FORCE;
-                }
-            }
+        let x = x * 200.0 * dd;
+        let y = y * 200.0 * dd;
 
-            steers.push(steer);
-        }
+        let vx = rng.gen::<f32>() * 2.0 - 1.0;
+        let vy = rng.gen::<f32>() * 2.0 - 1.0;
 
-        steers
+        (Vec2::new(x, y), Vec2::new(vx, vy))
     }
+}
 
-    fn align(velocities: &[Vec2], positions: &[Vec2]) -> Vec<Vec2> {
-        let neighbor_dist = 100.0;
-
-        let mut aligns = Vec::new();
+impl Scatter for Vec3 {
+    fn scatter(rng: &mut impl Rng) -> (Self, Self) {
+        let mut unit = || Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 2.0 - Vec3::ONE;
 
-        for i in 0..positions.len() {
-            let mut sum = Vec2::ZERO;
-            let mut count = 0;
+        // Uniform in a ball, by rejecting what falls in the corners of the cube
+        let mut position = unit();
+        while position.length_squared() > 1.0 {
+            position = unit();
+        }
 
-            for j in 0..positions.len() {
-                let dist = (positions[i] - positions[j]).length();
-                if dist > 0.0 && dist < neighbor_dist {
-                    sum += velocities[j];
-                    count += 1;
-                }
-            }
+        (position * 200.0, unit())
+    }
+}
 
-            if count > 0 {
-                sum /= count as f32;
+/// Marks the flock's entities, which carry a `BoidBundle`.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct Boid;
+
+/// Where a boid is in the simulation, `Transform` only follows it for rendering.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct Position<V: Vector = Vec2>(pub V);
+
+/// Position before the last step, rendering interpolates from it.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct PreviousPosition<V: Vector = Vec2>(pub V);
+
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct Velocity<V: Vector = Vec2>(pub V);
+
+/// Steering force for the next step.
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct Acceleration<V: Vector = Vec2>(pub V);
+
+/// Index of the boid's species in `BoidsConfig::species`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub struct SpeciesId(pub usize);
+
+/// Everything the simulation needs on a boid, rendering is added on top.
+#[derive(Debug, Default, Clone, Bundle)]
+pub struct BoidBundle<V: Vector = Vec2> {
+    pub boid: Boid,
+    pub position: Position<V>,
+    pub previous_position: PreviousPosition<V>,
+    pub velocity: Velocity<V>,
+    pub acceleration: Acceleration<V>,
+    pub species: SpeciesId,
+}
 
-                sum = sum.normalize();
-                sum *= Boids::MAX_SPEED;
-                let mut steer = sum - velocities[i];
-                if steer.length() > Boids::MAX_FORCE {
-                    steer = steer.normalize();
-                    steer *= Boids::MAX_FORCE;
-                }
-                aligns.push(steer);
-            } else {
-                aligns.push(Vec2::ZERO);
-            }
+impl<V: Vector> BoidBundle<V> {
+    pub fn new(position: V, velocity: V, species: usize) -> Self {
+        Self {
+            boid: Boid,
+            position: Position(position),
+            previous_position: PreviousPosition(position),
+            velocity: Velocity(velocity),
+            acceleration: Acceleration(V::ZERO),
+            species: SpeciesId(species),
         }
+    }
+}
 
-        aligns
+/// Scatters the configured number of boids of every species over a disc (or
+/// ball) around the origin, heading in random directions. The same generator
+/// state always gives the same flock.
+pub fn random_flock<V: Scatter>(rng: &mut impl Rng, config: &BoidsConfig) -> Vec<BoidBundle<V>> {
+    let mut flock = Vec::new();
+
+    for (species, species_config) in config.species.iter().enumerate() {
+        let max_speed = species_config.flocking.max_speed;
+        for _ in 0..species_config.count {
+            let (position, velocity) = V::scatter(rng);
+            flock.push(BoidBundle::new(position, velocity * max_speed, species));
+        }
     }
 
-    fn cohesion(velocities: &[Vec2], positions: &[Vec2]) -> Vec<Vec2> {
-        let neighbor_dist = 75.0;
+    flock
+}
 
-        let mut cohesions = Vec::new();
+/// Advances a boid by `dt` seconds with semi-implicit Euler: the velocity is
+/// updated first and the new velocity moves the boid. The acceleration is used
+/// up.
+pub fn integrate<V: Vector>(
+    position: &mut V,
+    velocity: &mut V,
+    acceleration: &mut V,
+    max_speed: f32,
+    dt: f32,
+) {
+    *velocity += *acceleration * dt;
+
+    if velocity.length() > max_speed {
+        *velocity = velocity.normalize() * max_speed;
+    }
+    *position += *velocity * dt;
+    *acceleration = V::ZERO;
+}
 
-        for i in 0..positions.len() {
-            let mut sum = Vec2::ZERO;
-            let mut count = 0;
+/// Teleports a boid that left `bounds` to the opposite side. Its previous
+/// position moves along so interpolation doesn't streak across the world.
+pub fn wrap<V: Vector>(position: &mut V, previous: &mut V, bounds: &AABB<V>) {
+    let min = bounds.min();
+    let max = bounds.max();
+    let size = max - min;
 
-            for j in 0..positions.len() {
-                let dist = (positions[i] - positions[j]).length();
-                let dist = dist.abs();
-                if dist > 0.0 && dist < neighbor_dist {
-                    sum += positions[j];
-                    count += 1;
-                }
-            }
+    let mut offset = V::ZERO;
 
-            if count > 0 {
-                sum /= count as f32;
-                cohesions.push(Boids::seek(sum, positions[i], velocities[i]));
-            } else {
-                cohesions.push(Vec2::ZERO);
-            }
+    for axis in 0..V::DIM {
+        if position.component(axis) < min.component(axis) {
+            offset += V::axis(axis) * size.component(axis);
+        } else if position.component(axis) > max.component(axis) {
+            offset -= V::axis(axis) * size.component(axis);
         }
+    }
 
-        cohesions
+    *position += offset;
+    *previous += offset;
+}
+
+/// Mirrors a boid that left `bounds` back inside, turning its velocity around
+/// on the axes it crossed.
+pub fn reflect<V: Vector>(position: &mut V, velocity: &mut V, bounds: &AABB<V>) {
+    let min = bounds.min();
+    let max = bounds.max();
+
+    for axis in 0..V::DIM {
+        let unit = V::axis(axis);
+        let overshoot = if position.component(axis) < min.component(axis) {
+            position.component(axis) - min.component(axis)
+        } else if position.component(axis) > max.component(axis) {
+            position.component(axis) - max.component(axis)
+        } else {
+            continue;
+        };
+
+        *position -= unit * (overshoot * 2.0);
+        *velocity -= unit * (velocity.component(axis) * 2.0);
     }
+}
 
-    fn seek(target: Vec2, position: Vec2, velocity: Vec2) -> Vec2 {
-        let desired = target - position;
-        let desired = desired.normalize() * Boids::MAX_SPEED;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closest_point_on_obstacles() {
+        let circle = Obstacle::Circle { radius: 10.0 };
+        assert_eq!(
+            circle.closest_point(Vec2::ZERO, Vec2::new(20.0, 0.0)),
+            Vec2::new(10.0, 0.0)
+        );
+        assert_eq!(
+            circle.closest_point(Vec2::ZERO, Vec2::new(5.0, 0.0)),
+            Vec2::new(5.0, 0.0)
+        );
+
+        let rect = Obstacle::Rect {
+            half_extents: Vec2::new(10.0, 5.0),
+        };
+        assert_eq!(
+            rect.closest_point(Vec2::new(100.0, 0.0), Vec2::new(120.0, 20.0)),
+            Vec2::new(110.0, 5.0)
+        );
+    }
 
-        let mut steer = desired - velocity;
+    #[test]
+    fn wrapping_keeps_the_step_length() {
+        let bounds = AABB::new(Vec2::ZERO, Vec2::new(100.0, 50.0));
+        let mut previous = Vec2::new(98.0, 0.0);
+        let mut position = Vec2::new(103.0, 0.0);
 
-        if steer.length() > Boids::MAX_FORCE {
-            steer = steer.normalize();
-            steer *= Boids::MAX_FORCE;
-        }
-        steer
+        wrap(&mut position, &mut previous, &bounds);
+        assert_eq!(position, Vec2::new(-97.0, 0.0));
+        assert_eq!(position - previous, Vec2::new(5.0, 0.0));
     }
 
-    pub fn flock(
-        accelerations: &mut [bevy::prelude::Vec2],
-        velocities: &[Vec2],
-        positions: &[Vec2],
-    ) {
-        let mut sep = Boids::separate(velocities, positions);
-        let mut ali = Boids::align(velocities, positions);
-        let mut coh = Boids::cohesion(velocities, positions);
-
-        for i in 0..sep.len() {
-            sep[i] *= 1.5;
-            ali[i] *= 1.0;
-            coh[i] *= 1.0;
-
-            accelerations[i] += sep[i];
-            accelerations[i] += ali[i];
-            accelerations[i] += coh[i];
-        }
+    #[test]
+    fn reflecting_bounces_off_the_crossed_edges() {
+        let bounds = AABB::new(Vec2::ZERO, Vec2::new(100.0, 50.0));
+        let mut position = Vec2::new(103.0, 10.0);
+        let mut velocity = Vec2::new(5.0, 2.0);
+
+        reflect(&mut position, &mut velocity, &bounds);
+        assert_eq!(position, Vec2::new(97.0, 10.0));
+        assert_eq!(velocity, Vec2::new(-5.0, 2.0));
+
+        let mut position = Vec2::new(-101.0, -52.0);
+        let mut velocity = Vec2::new(-1.0, -4.0);
+        reflect(&mut position, &mut velocity, &bounds);
+        assert_eq!(position, Vec2::new(-99.0, -48.0));
+        assert_eq!(velocity, Vec2::new(1.0, 4.0));
     }
 }
@@ -0,0 +1,333 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+/// The species making up the flock and how they get along, loaded from
+/// `assets/boids/flocking.ron`.
+///
+/// The file is watched, so saving it while the simulation runs applies the new
+/// values straight away. Fields missing from the file keep their default value.
+#[derive(Debug, Clone, PartialEq, Deserialize, Resource, TypeUuid)]
+#[uuid = "5a4b2c8e-7d3f-4f5e-9b1a-2e6c8d0f4a71"]
+#[serde(default)]
+pub struct BoidsConfig {
+    pub species: Vec<Species>,
+    /// Pairs of species that don't simply ignore each other.
+    pub interactions: Vec<Interaction>,
+}
+
+impl Default for BoidsConfig {
+    fn default() -> Self {
+        Self {
+            species: vec![Species::default()],
+            interactions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Species {
+    pub name: String,
+    /// Sprite colour as linear RGB.
+    pub color: [f32; 3],
+    /// Number of boids spawned at startup.
+    pub count: usize,
+    pub flocking: FlockingConfig,
+}
+
+impl Default for Species {
+    fn default() -> Self {
+        Self {
+            name: "boid".to_string(),
+            color: [1.0, 0.0, 0.0],
+            count: 100,
+            flocking: FlockingConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Interaction {
+    pub between: (String, String),
+    pub relation: Relation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Relation {
+    /// Both species act as if the other wasn't there.
+    Ignore,
+    /// Both species keep their separation distance from the other.
+    Avoid,
+    /// The first species seeks the second, which flees from it.
+    Hunts,
+}
+
+/// How a boid treats a neighbour, depending on both their species.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reaction {
+    Flock,
+    Ignore,
+    Avoid,
+    Hunt,
+    Flee,
+}
+
+impl BoidsConfig {
+    /// Largest distance any species looks at its neighbours from.
+    pub fn neighbor_radius(&self) -> f32 {
+        let has_prey = self
+            .interactions
+            .iter()
+            .any(|interaction| interaction.relation == Relation::Hunts);
+
+        self.species
+            .iter()
+            .map(|species| {
+                let radius = species.flocking.neighbor_radius();
+                if has_prey {
+                    radius.max(species.flocking.flee_radius)
+                } else {
+                    radius
+                }
+            })
+            .fold(0.0, f32::max)
+    }
+
+    /// `reactions()[a][b]` is how species `a` reacts to a boid of species `b`.
+    /// Interactions naming unknown species are skipped.
+    pub fn reactions(&self) -> Vec<Vec<Reaction>> {
+        let count = self.species.len();
+        let mut reactions = vec![vec![Reaction::Ignore; count]; count];
+        for (species, row) in reactions.iter_mut().enumerate() {
+            row[species] = Reaction::Flock;
+        }
+
+        let index = |name: &str| self.species.iter().position(|species| species.name == name);
+
+        for interaction in &self.interactions {
+            let (first, second) = &interaction.between;
+            if let (Some(a), Some(b)) = (index(first), index(second)) {
+                if a == b {
+                    continue;
+                }
+                let (forward, backward) = match interaction.relation {
+                    Relation::Ignore => (Reaction::Ignore, Reaction::Ignore),
+                    Relation::Avoid => (Reaction::Avoid, Reaction::Avoid),
+                    Relation::Hunts => (Reaction::Hunt, Reaction::Flee),
+                };
+                reactions[a][b] = forward;
+                reactions[b][a] = backward;
+            }
+        }
+
+        reactions
+    }
+}
+
+/// Tuning of the flocking rules for one species. Speeds are in pixels per second
+/// and forces in pixels per second squared.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FlockingConfig {
+    pub max_speed: f32,
+    pub max_force: f32,
+    pub separation_radius: f32,
+    pub alignment_radius: f32,
+    pub cohesion_radius: f32,
+    pub separation_weight: f32,
+    pub alignment_weight: f32,
+    pub cohesion_weight: f32,
+    /// Distance at which boids start to turn away from obstacles.
+    pub avoidance_radius: f32,
+    /// Distance at which boids notice threats.
+    pub flee_radius: f32,
+    pub avoidance_weight: f32,
+    pub seek_weight: f32,
+    pub flee_weight: f32,
+    /// Pull towards the closest prey.
+    pub hunt_weight: f32,
+    /// Push away from species this one avoids, within `separation_radius`.
+    pub avoid_species_weight: f32,
+    pub avoid_obstacles: bool,
+    pub seek_targets: bool,
+    pub flee_threats: bool,
+}
+
+impl Default for FlockingConfig {
+    fn default() -> Self {
+        Self {
+            max_speed: 480.0,
+            max_force: 3600.0,
+            separation_radius: 25.0,
+            alignment_radius: 100.0,
+            cohesion_radius: 75.0,
+            separation_weight: 1.5,
+            alignment_weight: 1.0,
+            cohesion_weight: 1.0,
+            avoidance_radius: 40.0,
+            flee_radius: 120.0,
+            avoidance_weight: 3.0,
+            seek_weight: 0.5,
+            flee_weight: 2.0,
+            hunt_weight: 1.0,
+            avoid_species_weight: 1.5,
+            avoid_obstacles: true,
+            seek_targets: true,
+            flee_threats: true,
+        }
+    }
+}
+
+impl FlockingConfig {
+    /// Largest radius used by the flocking rules, anything further away is ignored.
+    pub fn neighbor_radius(&self) -> f32 {
+        self.separation_radius
+            .max(self.alignment_radius)
+            .max(self.cohesion_radius)
+    }
+
+    /// The numeric parameter called `name` in the config file.
+    pub fn parameter_mut(&mut self, name: &str) -> Option<&mut f32> {
+        Some(match name {
+            "max_speed" => &mut self.max_speed,
+            "max_force" => &mut self.max_force,
+            "separation_radius" => &mut self.separation_radius,
+            "alignment_radius" => &mut self.alignment_radius,
+            "cohesion_radius" => &mut self.cohesion_radius,
+            "separation_weight" => &mut self.separation_weight,
+            "alignment_weight" => &mut self.alignment_weight,
+            "cohesion_weight" => &mut self.cohesion_weight,
+            "avoidance_radius" => &mut self.avoidance_radius,
+            "flee_radius" => &mut self.flee_radius,
+            "avoidance_weight" => &mut self.avoidance_weight,
+            "seek_weight" => &mut self.seek_weight,
+            "flee_weight" => &mut self.flee_weight,
+            "hunt_weight" => &mut self.hunt_weight,
+            "avoid_species_weight" => &mut self.avoid_species_weight,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct BoidsConfigLoader;
+
+impl AssetLoader for BoidsConfigLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let config = ron::de::from_bytes::<BoidsConfig>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(config));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["flocking.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct BoidsConfigHandle(pub Handle<BoidsConfig>);
+
+pub fn load_boids_config_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(BoidsConfigHandle(asset_server.load("boids/flocking.ron")));
+}
+
+/// Copies the config asset into the `BoidsConfig` resource whenever it is
+/// (re)loaded.
+pub fn apply_boids_config_system(
+    mut events: EventReader<AssetEvent<BoidsConfig>>,
+    handle: Res<BoidsConfigHandle>,
+    assets: Res<Assets<BoidsConfig>>,
+    mut config: ResMut<BoidsConfig>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle.0 =>
+            {
+                if let Some(loaded) = assets.get(changed) {
+                    info!("Applying boids config {:?}", loaded);
+                    *config = loaded.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_config_parses() {
+        let config: BoidsConfig =
+            ron::from_str(include_str!("../../../assets/boids/flocking.ron")).unwrap();
+        assert_eq!(config.species[0].flocking, FlockingConfig::default());
+        assert!(config.species.len() > 1);
+    }
+
+    #[test]
+    fn missing_fields_keep_defaults() {
+        let config: FlockingConfig = ron::from_str("(max_speed: 4.0)").unwrap();
+        assert_eq!(config.max_speed, 4.0);
+        assert_eq!(
+            config.cohesion_radius,
+            FlockingConfig::default().cohesion_radius
+        );
+        assert_eq!(config.neighbor_radius(), 100.0);
+
+        let config: BoidsConfig = ron::from_str("()").unwrap();
+        assert_eq!(config, BoidsConfig::default());
+    }
+
+    #[test]
+    fn reactions_between_species() {
+        let species = |name: &str| Species {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let config: BoidsConfig = BoidsConfig {
+            species: vec![species("sparrow"), species("hawk"), species("crow")],
+            interactions: vec![
+                Interaction {
+                    between: ("hawk".to_string(), "sparrow".to_string()),
+                    relation: Relation::Hunts,
+                },
+                Interaction {
+                    between: ("crow".to_string(), "hawk".to_string()),
+                    relation: Relation::Avoid,
+                },
+                Interaction {
+                    between: ("crow".to_string(), "owl".to_string()),
+                    relation: Relation::Hunts,
+                },
+            ],
+        };
+
+        let reactions = config.reactions();
+        assert_eq!(reactions[0][0], Reaction::Flock);
+        assert_eq!(reactions[1][0], Reaction::Hunt);
+        assert_eq!(reactions[0][1], Reaction::Flee);
+        assert_eq!(reactions[2][1], Reaction::Avoid);
+        assert_eq!(reactions[1][2], Reaction::Avoid);
+        assert_eq!(reactions[0][2], Reaction::Ignore);
+        assert_eq!(reactions[2][0], Reaction::Ignore);
+
+        // Prey has to notice predators from its flee radius
+        assert_eq!(
+            config.neighbor_radius(),
+            FlockingConfig::default().flee_radius
+        );
+    }
+}
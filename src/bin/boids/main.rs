@@ -1,75 +1,287 @@
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
+use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
-use spatial_partition::{QuadTree, AABB};
+use bevy::time::{FixedTimestep, FixedTimesteps};
+use bevy_prototype_lyon::prelude::*;
+use boids::{
+    Acceleration, Boid, BoidBundle, Edges, Position, PreviousPosition, Scatter, SpeciesId, Velocity,
+};
+use config::{BoidsConfig, FlockingConfig, Reaction};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use spatial_partition::{SpatialHashGrid, SpatialIndex, Tree, AABB};
+use steering::FlockSteering;
+use vector::Vector;
 
+mod analysis;
 mod boids;
-
-#[derive(Component)]
-struct Square;
+mod config;
+mod overlay;
+mod recording;
+mod steering;
+mod sweep;
+mod vector;
 
 fn setup(mut commands: Commands) {
     commands.spawn(Camera2dBundle::default());
 }
 
-#[derive(Debug, Component)]
-struct Boid {
-    id: usize,
+fn setup_3d(mut commands: Commands) {
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 1_000_000.0,
+            range: 2000.0,
+            ..Default::default()
+        },
+        transform: Transform::from_translation(Vec3::new(400.0, 600.0, 400.0)),
+        ..Default::default()
+    });
+    commands.spawn(Camera3dBundle {
+        transform: Transform::from_translation(Vec3::new(300.0, 400.0, 900.0))
+            .looking_at(Vec3::default(), Vec3::Y),
+        ..Default::default()
+    });
 }
 
-mod spatial_partition;
+/// Spatial partition of the flock, kept up to date as boids move rather than
+/// rebuilt every frame.
+#[derive(Resource, Deref, DerefMut)]
+struct BoidsPartition<V: Vector = Vec2>(Box<dyn SpatialIndex<Entity, V> + Send + Sync>);
 
-fn boids_system(
-    mut commands: Commands,
-    mut boids: Local<boids::Boids>,
-    // mut boids_partition: ResMut<QuadTree>,
-    mut boid_sprites: Query<(&Boid, &mut Transform)>,
-    windows: Res<Windows>,
+/// Spatial partition a flock is indexed with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Resource)]
+enum Index {
+    /// A quadtree in 2D and an octree in 3D, covering the world.
+    #[default]
+    Tree,
+    /// A spatial hash grid with cells as large as the neighbourhood.
+    Grid,
+}
+
+impl Index {
+    /// Picks the partition from the command line, `--index grid` selects the
+    /// spatial hash grid and anything else a tree.
+    fn from_args() -> Self {
+        match arg_value("--index").as_deref() {
+            Some("grid") => Index::Grid,
+            _ => Index::Tree,
+        }
+    }
+}
+
+impl<V: Vector> BoidsPartition<V> {
+    fn new(index: Index, world_bounds: AABB<V>, neighbor_radius: f32) -> Self {
+        match index {
+            Index::Tree => Self(Box::new(Tree::new(world_bounds))),
+            Index::Grid => Self(Box::new(SpatialHashGrid::new(neighbor_radius))),
+        }
+    }
+}
+
+/// Rebuilds a grid partition whenever the loaded config changes the size of the
+/// neighbourhood, so its cells stay as large as the neighbourhood.
+fn resize_grid_system<V: Vector>(
+    config: Res<BoidsConfig>,
+    index: Res<Index>,
+    world_bounds: Res<WorldBounds<V>>,
+    mut boids_partition: ResMut<BoidsPartition<V>>,
+    mut cell_size: Local<Option<f32>>,
+    boids: Query<(Entity, &Position<V>), With<Boid>>,
 ) {
-    const NUM_BOIDS: usize = 100;
+    let radius = config.neighbor_radius();
+    if *index != Index::Grid || *cell_size == Some(radius) {
+        return;
+    }
 
-    let mut boids_partition = QuadTree::new(AABB::new(Vec2::ZERO, Vec2::new(800.0, 800.0)));
+    *boids_partition = BoidsPartition::new(Index::Grid, world_bounds.aabb, radius);
+    for (entity, position) in boids.iter() {
+        boids_partition.insert(position.0, entity);
+    }
+    *cell_size = Some(radius);
+}
 
-    if boids.size() < 10 {
-        let width = 4.0;
-        let height = 4.0;
+mod spatial_partition;
 
-        for i in 0..NUM_BOIDS {
-            let d = rand::random::<f32>();
-            let dd = rand::random::<f32>();
-            let r = d * std::f32::consts::TAU;
-            // let x = (rand::random::<f32>() * 2.0 - 1.0) * r * 300.0;
-            // let y = (rand::random::<f32>() * 2.0 - 1.0) * r * 300.0;
+/// Length of a simulation step, the flock advances by exactly this much however
+/// fast frames are rendered.
+const TIMESTEP: f64 = 1.0 / 60.0;
+const TIMESTEP_LABEL: &str = "boids";
 
-            let x = f32::sin(r) * 2.0 - 1.0;
-            let y = f32::cos(r) * 2.0 - 1.0;
+/// Box boids live in and what happens at its edges. It doesn't depend on the
+/// window, so the flock runs the same headless.
+#[derive(Resource)]
+struct WorldBounds<V: Vector = Vec2> {
+    aabb: AABB<V>,
+    edges: Edges,
+}
 
-            let x = x * 200.0 * dd;
-            let y = y * 200.0 * dd;
+impl<V: Vector> WorldBounds<V> {
+    fn new(aabb: AABB<V>, edges: Edges) -> Self {
+        Self { aabb, edges }
+    }
 
-            boids.add(Vec2::new(x, y));
+    /// Picks the edges from the command line, `--edges reflect` bounces boids
+    /// off them, `--edges steer` turns boids away from them and anything else
+    /// wraps boids around.
+    fn from_args(aabb: AABB<V>) -> Self {
+        let edges = match arg_value("--edges").as_deref() {
+            Some("reflect") => Edges::Reflect,
+            Some("steer") => Edges::Steer,
+            _ => Edges::Wrap,
+        };
+        Self::new(aabb, edges)
+    }
 
-            boids_partition.insert(Vec2::new(x, y), i);
+    /// Entries of `index` within `radius` of `center`, looking across the edges
+    /// when boids wrap around them.
+    fn query_radius<T>(
+        &self,
+        index: &dyn SpatialIndex<T, V>,
+        center: V,
+        radius: f32,
+    ) -> Vec<(V, T)> {
+        match self.edges {
+            Edges::Wrap => index.query_radius_wrapped(center, radius, &self.aabb),
+            Edges::Reflect | Edges::Steer => index.query_radius(center, radius),
+        }
+    }
 
-            commands.spawn((
-                SpriteBundle {
-                    sprite: Sprite {
-                        custom_size: Some(Vec2::new(width as f32, height as f32)),
-                        color: Color::RED,
-                        ..Default::default()
-                    },
-                    transform: Transform::from_translation(Vec3::new(x, y, 0.0)),
-                    ..Default::default()
-                },
-                Boid { id: i },
-            ));
+    /// The `k` entries of `index` closest to `point`, looking across the edges
+    /// when boids wrap around them.
+    fn k_nearest<T: PartialEq>(
+        &self,
+        index: &dyn SpatialIndex<T, V>,
+        point: V,
+        k: usize,
+    ) -> Vec<(V, T)> {
+        match self.edges {
+            Edges::Wrap => index.k_nearest_wrapped(point, k, &self.aabb),
+            Edges::Reflect | Edges::Steer => index.k_nearest(point, k),
         }
-    } else {
-        for i in 0..boids.positions.len() {
-            boids_partition.insert(boids.positions[i], i);
+    }
+
+    /// Brings a boid that left the world back inside.
+    fn contain(&self, position: &mut V, previous: &mut V, velocity: &mut V) {
+        match self.edges {
+            Edges::Wrap => boids::wrap(position, previous, &self.aabb),
+            Edges::Reflect | Edges::Steer => boids::reflect(position, velocity, &self.aabb),
         }
     }
 
-    // // println!("{:#?}", *boids_partition);
+    /// Walls boids steer clear of, if they do.
+    fn walls(&self) -> Option<AABB<V>> {
+        (self.edges == Edges::Steer).then_some(self.aabb)
+    }
+}
+
+/// Seed of the random number generator the flock is set up from, runs started
+/// with the same `--seed N` produce the same flock.
+#[derive(Resource)]
+struct BoidsSeed(u64);
+
+impl BoidsSeed {
+    fn from_args() -> Self {
+        let seed = arg_value("--seed")
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+        Self(seed)
+    }
+
+    fn rng(&self) -> StdRng {
+        StdRng::seed_from_u64(self.0)
+    }
+}
+
+/// Prints the seed, so a run can be replayed with `--seed`.
+fn log_seed_system(seed: Res<BoidsSeed>) {
+    info!("Boids seed: {}", seed.0);
+}
+
+/// Value following `name` on the command line.
+fn arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+/// Name, count and colour of every species, what the spawned flock depends on.
+type SpeciesLayout = Vec<(String, usize, [f32; 3])>;
+
+/// Whether the flock has to be spawned again, the first time and whenever the
+/// config changes which species there are, their colour or how many of them.
+fn respawn_needed(config: &BoidsConfig, spawned: &mut Option<SpeciesLayout>) -> bool {
+    let layout = config
+        .species
+        .iter()
+        .map(|species| (species.name.clone(), species.count, species.color))
+        .collect::<SpeciesLayout>();
+    if spawned.as_ref() == Some(&layout) {
+        return false;
+    }
+    *spawned = Some(layout);
+    true
+}
+
+/// Spawns a boid and adds it to the partition, the caller inserts whatever
+/// renders it.
+fn spawn_boid<'w, 's, 'a, V: Vector>(
+    commands: &'a mut Commands<'w, 's>,
+    boids_partition: &mut BoidsPartition<V>,
+    boid: BoidBundle<V>,
+) -> EntityCommands<'w, 's, 'a> {
+    let position = boid.position.0;
+    let entity = commands.spawn(boid);
+    boids_partition.insert(position, entity.id());
+    entity
+}
+
+/// Despawns a boid and takes it out of the partition.
+fn despawn_boid<V: Vector>(
+    commands: &mut Commands,
+    boids_partition: &mut BoidsPartition<V>,
+    entity: Entity,
+) {
+    commands.entity(entity).despawn();
+    boids_partition.remove_by_id(&entity);
+}
+
+fn boid_sprite(color: [f32; 3], position: Vec2) -> SpriteBundle {
+    let width = 4.0;
+    let height = 4.0;
+    let [r, g, b] = color;
+
+    SpriteBundle {
+        sprite: Sprite {
+            custom_size: Some(Vec2::new(width as f32, height as f32)),
+            color: Color::rgb(r, g, b),
+            ..Default::default()
+        },
+        transform: Transform::from_translation(position.extend(0.0)),
+        ..Default::default()
+    }
+}
+
+/// Spawns the flock, and spawns it again from the seed whenever the config
+/// changes which species there are, their colour or how many of them.
+fn spawn_boids_system(
+    mut commands: Commands,
+    mut boids_partition: ResMut<BoidsPartition>,
+    world_bounds: Res<WorldBounds>,
+    seed: Res<BoidsSeed>,
+    config: Res<BoidsConfig>,
+    boids: Query<Entity, With<Boid>>,
+    mut spawned: Local<Option<SpeciesLayout>>,
+) {
+    if !respawn_needed(&config, &mut spawned) {
+        return;
+    }
+
+    for entity in boids.iter() {
+        despawn_boid(&mut commands, &mut boids_partition, entity);
+    }
+    for mut boid in boids::random_flock::<Vec2>(&mut seed.rng(), &config) {
+        contain_boid(&world_bounds, &mut boid);
+        let sprite = boid_sprite(config.species[boid.species.0].color, boid.position.0);
+        spawn_boid(&mut commands, &mut boids_partition, boid).insert(sprite);
+    }
 
     // let sprites = boids
     //     .positions
@@ -91,131 +303,1025 @@ fn boids_system(
     // // println!("{:#?}", sprites.len());
 
     // commands.spawn_batch(sprites);
+}
+
+/// Same as `spawn_boids_system` with a small sphere per boid.
+#[allow(clippy::too_many_arguments)]
+fn spawn_boids_3d_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut boids_partition: ResMut<BoidsPartition<Vec3>>,
+    world_bounds: Res<WorldBounds<Vec3>>,
+    seed: Res<BoidsSeed>,
+    config: Res<BoidsConfig>,
+    boids: Query<Entity, With<Boid>>,
+    mut spawned: Local<Option<SpeciesLayout>>,
+) {
+    if !respawn_needed(&config, &mut spawned) {
+        return;
+    }
+
+    for entity in boids.iter() {
+        despawn_boid(&mut commands, &mut boids_partition, entity);
+    }
+
+    let mesh = meshes.add(Mesh::from(shape::Icosphere {
+        radius: 3.0,
+        subdivisions: 1,
+    }));
+    let species_materials = config
+        .species
+        .iter()
+        .map(|species| {
+            let [r, g, b] = species.color;
+            materials.add(Color::rgb(r, g, b).into())
+        })
+        .collect::<Vec<_>>();
 
-    let window = windows.get_primary().unwrap();
+    for mut boid in boids::random_flock::<Vec3>(&mut seed.rng(), &config) {
+        contain_boid(&world_bounds, &mut boid);
+        let pbr = PbrBundle {
+            mesh: mesh.clone(),
+            material: species_materials[boid.species.0].clone(),
+            transform: Transform::from_translation(boid.position.0),
+            ..Default::default()
+        };
+        spawn_boid(&mut commands, &mut boids_partition, boid).insert(pbr);
+    }
+}
 
-    let half_window_width = window.width() / 2.0;
-    let half_window_height = window.height() / 2.0;
+/// Moves a boid scattered outside the world inside, so it is in the partition
+/// from the start.
+fn contain_boid<V: Vector>(world_bounds: &WorldBounds<V>, boid: &mut BoidBundle<V>) {
+    world_bounds.contain(
+        &mut boid.position.0,
+        &mut boid.previous_position.0,
+        &mut boid.velocity.0,
+    );
+}
 
-    let mut new_accelerations = Vec::new();
-    new_accelerations.resize_with(boids.accelerations.len(), || Vec2::ZERO);
+/// Cursor in world coordinates of the 2D camera.
+fn cursor_position(windows: &Windows) -> Option<Vec2> {
+    let window = windows.get_primary()?;
+    // Measured from the bottom left corner, the camera looks at the centre
+    let cursor = window.cursor_position()?;
+    Some(cursor - Vec2::new(window.width(), window.height()) / 2.0)
+}
 
-    for id in 0..boids.size() {
-        let boid = boids.positions[id];
+/// Boids this close to the cursor are removed by a right click.
+const REMOVE_RADIUS: f32 = 20.0;
 
-        let data = boids_partition
-            .query_range(&AABB::new(Vec2::new(boid.x, boid.y), Vec2::new(30.0, 30.0)));
+/// Left click adds a boid of the first species at the cursor, heading
+/// somewhere random. Right click removes the boids around the cursor.
+fn edit_flock_system(
+    mut commands: Commands,
+    mut boids_partition: ResMut<BoidsPartition>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    config: Res<BoidsConfig>,
+) {
+    let cursor = match cursor_position(&windows) {
+        Some(cursor) => cursor,
+        None => return,
+    };
 
-        if data.is_empty() {
-            continue;
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        if let Some(species) = config.species.first() {
+            let (_, heading) = Vec2::scatter(&mut rand::thread_rng());
+            let boid = BoidBundle::new(cursor, heading * species.flocking.max_speed, 0);
+            spawn_boid(&mut commands, &mut boids_partition, boid)
+                .insert(boid_sprite(species.color, cursor));
         }
+    }
 
-        let mut accelerations = data
-            .iter()
-            .map(|(_, bid)| boids.accelerations[*bid])
-            .collect::<Vec<Vec2>>();
+    if mouse_buttons.just_pressed(MouseButton::Right) {
+        for (_, entity) in boids_partition.query_radius(cursor, REMOVE_RADIUS) {
+            despawn_boid(&mut commands, &mut boids_partition, entity);
+        }
+    }
+}
+
+const TARGET_KEY: KeyCode = KeyCode::T;
+const THREAT_KEY: KeyCode = KeyCode::F;
+const MARKER_RADIUS: f32 = 8.0;
+
+/// `T` drops a target under the cursor and `F` a threat, either key removes
+/// the target or threat already there instead.
+#[allow(clippy::type_complexity)]
+fn place_markers_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    markers: Query<(Entity, &Transform), Or<(With<boids::Target>, With<boids::Threat>)>>,
+) {
+    if !keys.any_just_pressed([TARGET_KEY, THREAT_KEY]) {
+        return;
+    }
+    let cursor = match cursor_position(&windows) {
+        Some(cursor) => cursor,
+        None => return,
+    };
+
+    if let Some((entity, _)) = markers
+        .iter()
+        .find(|(_, transform)| transform.translation.truncate().distance(cursor) <= REMOVE_RADIUS)
+    {
+        commands.entity(entity).despawn();
+        return;
+    }
+
+    let marker = |color| {
+        GeometryBuilder::build_as(
+            &shapes::Circle {
+                radius: MARKER_RADIUS,
+                center: Vec2::ZERO,
+            },
+            DrawMode::Fill(FillMode::color(color)),
+            Transform::from_translation(cursor.extend(0.0)),
+        )
+    };
+    if keys.just_pressed(TARGET_KEY) {
+        commands.spawn((marker(Color::GREEN), boids::Target));
+    } else {
+        commands.spawn((marker(Color::PURPLE), boids::Threat));
+    }
+}
+
+/// A couple of walls and a pillar for the flock to find its way around.
+fn spawn_obstacles_system(mut commands: Commands) {
+    let radius = 40.0;
+    commands.spawn((
+        GeometryBuilder::build_as(
+            &shapes::Circle {
+                radius,
+                center: Vec2::ZERO,
+            },
+            DrawMode::Fill(FillMode::color(Color::GRAY)),
+            Transform::from_translation(Vec3::new(0.0, 120.0, 0.0)),
+        ),
+        boids::Obstacle::<Vec2>::Circle { radius },
+    ));
 
-        let velocities = data
+    for (center, half_extents) in [
+        (Vec2::new(-150.0, 60.0), Vec2::new(20.0, 80.0)),
+        (Vec2::new(150.0, -60.0), Vec2::new(80.0, 20.0)),
+    ] {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(half_extents * 2.0),
+                    color: Color::GRAY,
+                    ..Default::default()
+                },
+                transform: Transform::from_translation(center.extend(0.0)),
+                ..Default::default()
+            },
+            boids::Obstacle::Rect { half_extents },
+        ));
+    }
+}
+
+/// Boids steered by one task, enough to make up for spawning it.
+const BOIDS_PER_TASK: usize = 256;
+
+/// The rest of the flock as seen by a steering boid.
+type Neighbors<'w, 's, V> = Query<'w, 's, (&'static Velocity<V>, &'static SpeciesId)>;
+
+/// What every boid looks at to pick its acceleration for the next step. Nothing
+/// moves until all of them are known, so they can be worked out in any order.
+struct FlockStep<'a, 'w, 's, V: Vector> {
+    neighbors: &'a Neighbors<'w, 's, V>,
+    boids_partition: &'a (dyn SpatialIndex<Entity, V> + Send + Sync),
+    world_bounds: &'a WorldBounds<V>,
+    surroundings: &'a boids::Surroundings<V>,
+    steering: &'a FlockSteering<V>,
+    config: &'a BoidsConfig,
+    reactions: Vec<Vec<Reaction>>,
+    neighbor_radius: f32,
+}
+
+impl<'a, 'w, 's, V: Vector> FlockStep<'a, 'w, 's, V> {
+    fn new(
+        neighbors: &'a Neighbors<'w, 's, V>,
+        boids_partition: &'a (dyn SpatialIndex<Entity, V> + Send + Sync),
+        world_bounds: &'a WorldBounds<V>,
+        surroundings: &'a boids::Surroundings<V>,
+        steering: &'a FlockSteering<V>,
+        config: &'a BoidsConfig,
+    ) -> Self {
+        Self {
+            neighbors,
+            boids_partition,
+            world_bounds,
+            surroundings,
+            steering,
+            config,
+            reactions: config.reactions(),
+            neighbor_radius: config.neighbor_radius(),
+        }
+    }
+
+    /// Steering force of the boid `entity`.
+    fn steer(&self, entity: Entity, position: V, velocity: V, species: usize) -> V {
+        let data =
+            self.world_bounds
+                .query_radius(self.boids_partition, position, self.neighbor_radius);
+
+        let mut positions = Vec::new();
+        let mut velocities = Vec::new();
+        let mut strangers = Vec::new();
+        let mut prey = Vec::new();
+        let mut predators = Vec::new();
+
+        for (pos, other) in data {
+            if other == entity {
+                continue;
+            }
+            // Boids spawned this frame are in the partition before they are in the world
+            let (other_velocity, other_species) = match self.neighbors.get(other) {
+                Ok(neighbor) => neighbor,
+                Err(_) => continue,
+            };
+            match self.reactions[species][other_species.0] {
+                Reaction::Flock => {
+                    positions.push(pos);
+                    velocities.push(other_velocity.0);
+                }
+                Reaction::Ignore => {}
+                Reaction::Avoid => strangers.push(pos),
+                Reaction::Hunt => prey.push(pos),
+                Reaction::Flee => predators.push(pos),
+            }
+        }
+
+        let neighborhood = steering::Neighborhood {
+            position,
+            velocity,
+            positions: &positions,
+            velocities: &velocities,
+            strangers: &strangers,
+            prey: &prey,
+            predators: &predators,
+            surroundings: self.surroundings,
+        };
+
+        self.steering.0[species].steer(&neighborhood, &self.config.species[species].flocking)
+    }
+}
+
+/// Runs once per fixed step, steering boids `BOIDS_PER_TASK` at a time on the
+/// compute task pool. Each boid only reads the rest of the flock, so this gives
+/// exactly what steering them one after the other does.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn boids_system<V: Vector>(
+    boids_partition: Res<BoidsPartition<V>>,
+    world_bounds: Res<WorldBounds<V>>,
+    steering: Res<FlockSteering<V>>,
+    config: Res<BoidsConfig>,
+    obstacles: Query<(&Transform, &boids::Obstacle<V>)>,
+    targets: Query<&Transform, With<boids::Target>>,
+    threats: Query<&Transform, With<boids::Threat>>,
+    neighbors: Neighbors<V>,
+    mut boids: Query<(
+        Entity,
+        &Position<V>,
+        &Velocity<V>,
+        &SpeciesId,
+        &mut Acceleration<V>,
+    )>,
+) {
+    let surroundings = boids::Surroundings {
+        obstacles: obstacles
+            .iter()
+            .map(|(transform, obstacle)| (V::from_translation(transform.translation), *obstacle))
+            .collect(),
+        targets: targets
             .iter()
-            .map(|(_, bid)| boids.velocities[*bid])
-            .collect::<Vec<Vec2>>();
+            .map(|transform| V::from_translation(transform.translation))
+            .collect(),
+        threats: threats
+            .iter()
+            .map(|transform| V::from_translation(transform.translation))
+            .collect(),
+        walls: world_bounds.walls(),
+    };
 
-        let positions = data.iter().map(|(pos, _)| *pos).collect::<Vec<Vec2>>();
+    let step = FlockStep::new(
+        &neighbors,
+        boids_partition.0.as_ref(),
+        &world_bounds,
+        &surroundings,
+        &steering,
+        &config,
+    );
 
-        // println!("{:#?} {:#?}", data, boid);
+    boids.par_for_each_mut(
+        BOIDS_PER_TASK,
+        |(entity, position, velocity, species, mut acceleration)| {
+            acceleration.0 = step.steer(entity, position.0, velocity.0, species.0);
+        },
+    );
+}
 
-        let index = data.iter().position(|&(_, bid)| bid == id).unwrap();
+/// Advances every boid by one `TIMESTEP` once they are all steered, keeping
+/// `boids_partition` up to date.
+#[allow(clippy::type_complexity)]
+fn move_boids_system<V: Vector>(
+    mut boids_partition: ResMut<BoidsPartition<V>>,
+    world_bounds: Res<WorldBounds<V>>,
+    config: Res<BoidsConfig>,
+    mut boids: Query<(
+        Entity,
+        &SpeciesId,
+        &mut Position<V>,
+        &mut PreviousPosition<V>,
+        &mut Velocity<V>,
+        &mut Acceleration<V>,
+    )>,
+) {
+    for (entity, species, mut position, mut previous, mut velocity, mut acceleration) in
+        boids.iter_mut()
+    {
+        let old_position = position.0;
+        previous.0 = old_position;
 
-        boids::Boids::flock(
-            accelerations.as_mut_slice(),
-            velocities.as_slice(),
-            positions.as_slice(),
+        boids::integrate(
+            &mut position.0,
+            &mut velocity.0,
+            &mut acceleration.0,
+            config.species[species.0].flocking.max_speed,
+            TIMESTEP as f32,
         );
+        world_bounds.contain(&mut position.0, &mut previous.0, &mut velocity.0);
 
-        // new_accelerations.extend(accelerations.iter().map(|&acc| acc * time.delta_seconds()));
-        new_accelerations[id] = accelerations[index];
+        boids_partition.update(entity, old_position, position.0);
     }
+}
 
-    boids.accelerations = new_accelerations;
+/// Moves the sprites to where the boids are between two fixed steps.
+#[allow(clippy::type_complexity)]
+fn boid_sprites_system<V: Vector>(
+    fixed_timesteps: Res<FixedTimesteps>,
+    mut boid_sprites: Query<(&PreviousPosition<V>, &Position<V>, &mut Transform), With<Boid>>,
+) {
+    let alpha = fixed_timesteps
+        .get(TIMESTEP_LABEL)
+        .map_or(1.0, |state| state.overstep_percentage() as f32);
 
-    boids.update();
+    for (previous, position, mut transform) in boid_sprites.iter_mut() {
+        transform.translation = previous.0.lerp(position.0, alpha).to_translation();
+    }
+}
 
-    // println!("{:#?}", boids_partition);
+/// Rebuilds the flock's configured behaviours whenever the config changes,
+/// behaviours added in code stay.
+fn steering_system<V: Vector>(config: Res<BoidsConfig>, mut steering: ResMut<FlockSteering<V>>) {
+    if config.is_changed() {
+        steering.configure(&config);
+    }
+}
 
-    // *boids_partition = QuadTree::new(boids_partition.boundary);
+/// One step of the flock.
+fn flock_systems<V: Vector>() -> SystemSet {
+    SystemSet::new()
+        .with_system(steering_system::<V>.before(boids_system::<V>))
+        .with_system(boids_system::<V>)
+        .with_system(move_boids_system::<V>.after(boids_system::<V>))
+}
+
+fn simulation_systems<V: Vector>() -> SystemSet {
+    flock_systems::<V>().with_run_criteria(FixedTimestep::step(TIMESTEP).with_label(TIMESTEP_LABEL))
+}
+
+/// Simulation and rendering of a flock in `V`'s dimensions, the spatial
+/// partition, world bounds and spawning are left to each mode. Every step is
+/// recorded with `--record <path>` on the command line, and its metrics written
+/// with `--metrics <path>`.
+fn add_flock<V: Vector>(app: &mut App) {
+    let mut simulation = simulation_systems::<V>();
+    if let Some(path) = arg_value("--record") {
+        let recorder = recording::Recorder::create::<V>(&path)
+            .unwrap_or_else(|err| panic!("Failed to create {}: {}", path, err));
+        app.insert_resource(recorder);
+        simulation =
+            simulation.with_system(recording::record_system::<V>.after(move_boids_system::<V>));
+    }
+    if let Some(path) = arg_value("--metrics") {
+        let log = analysis::MetricsLog::create(&path)
+            .unwrap_or_else(|err| panic!("Failed to create {}: {}", path, err));
+        app.insert_resource(log);
+    }
+    app.add_startup_system(analysis::setup_diagnostics_system);
+    simulation =
+        simulation.with_system(analysis::analysis_system::<V>.after(move_boids_system::<V>));
+
+    app.init_resource::<FlockSteering<V>>()
+        .insert_resource(Index::from_args())
+        .add_system(resize_grid_system::<V>.before(boids_system::<V>))
+        .add_system_set(simulation)
+        .add_system(boid_sprites_system::<V>.after(move_boids_system::<V>));
+}
 
-    for (boid, mut transform) in boid_sprites.iter_mut() {
-        // *boids_partition = QuadTree::new(boids_partition.boundary);
+fn add_2d(app: &mut App) {
+    // Fills the default window
+    let world_bounds = AABB::new(Vec2::ZERO, Vec2::new(640.0, 360.0));
 
-        // boids_partition.remove(boids.positions[boid.id]);
+    add_flock::<Vec2>(app);
+    app.add_plugin(ShapePlugin)
+        .insert_resource(BoidsPartition::new(
+            Index::from_args(),
+            world_bounds,
+            BoidsConfig::default().neighbor_radius(),
+        ))
+        .insert_resource(WorldBounds::from_args(world_bounds))
+        .add_startup_system(setup)
+        .add_startup_system(spawn_obstacles_system)
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            spawn_boids_system.after(config::apply_boids_config_system),
+        )
+        .add_system(edit_flock_system.after(move_boids_system::<Vec2>))
+        .add_system(place_markers_system);
+    overlay::add_overlay(app);
+}
 
-        let x = boids.positions[boid.id].x;
-        let y = boids.positions[boid.id].y;
+/// Boids flying in a box seen from outside, with `--3d` on the command line.
+fn add_3d(app: &mut App) {
+    let world_bounds = AABB::new(Vec3::ZERO, Vec3::splat(300.0));
+
+    add_flock::<Vec3>(app);
+    app.insert_resource(BoidsPartition::new(
+        Index::from_args(),
+        world_bounds,
+        BoidsConfig::default().neighbor_radius(),
+    ))
+    .insert_resource(WorldBounds::from_args(world_bounds))
+    .add_startup_system(setup_3d)
+    .add_system_to_stage(
+        CoreStage::PreUpdate,
+        spawn_boids_3d_system.after(config::apply_boids_config_system),
+    );
+}
 
-        if x < -half_window_width {
-            boids.positions[boid.id].x = half_window_width;
-        } else if x > half_window_width {
-            boids.positions[boid.id].x = -half_window_width;
+/// Runs the sweep at `path`, writing the results to `out` or the standard output.
+fn run_sweep(path: &str, out: Option<&str>) -> anyhow::Result<()> {
+    let sweep = sweep::Sweep::load(path)?;
+    match out {
+        Some(out) => {
+            let file = std::fs::File::create(out)
+                .map_err(|err| anyhow::anyhow!("Failed to create {}: {}", out, err))?;
+            sweep.run(&mut std::io::BufWriter::new(file))
         }
+        None => sweep.run(&mut std::io::stdout().lock()),
+    }
+}
 
-        if y < -half_window_height {
-            boids.positions[boid.id].y = half_window_height;
-        } else if y > half_window_height {
-            boids.positions[boid.id].y = -half_window_height;
+fn main() {
+    // Runs without a window, so it works on machines without a GPU
+    if let Some(path) = arg_value("--sweep") {
+        if let Err(err) = run_sweep(&path, arg_value("--out").as_deref()) {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
         }
+        return;
+    }
 
-        transform.translation.x = boids.positions[boid.id].x;
-        transform.translation.y = boids.positions[boid.id].y;
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(AssetPlugin {
+        watch_for_changes: true,
+        ..default()
+    }))
+    .add_asset::<BoidsConfig>()
+    .init_asset_loader::<config::BoidsConfigLoader>()
+    .init_resource::<BoidsConfig>()
+    // // Adds frame time diagnostics
+    .add_plugin(FrameTimeDiagnosticsPlugin::default())
+    // // Adds a system that prints diagnostics to the console
+    //        .add_plugin(PrintDiagnosticsPlugin::default())
+    // // Any plugin can register diagnostics
+    // // Uncomment this to add some render resource diagnostics:
+    // .add_plugin(bevy::wgpu::diagnostic::WgpuResourceDiagnosticsPlugin::default())
+    // // Uncomment this to add an entity count diagnostics:
+    // .add_plugin(bevy::diagnostic::EntityCountDiagnosticsPlugin::default())
+    // // Uncomment this to add an asset count diagnostics:
+    // .add_plugin(bevy::asset::diagnostic::AssetCountDiagnosticsPlugin::<
+    //     ColorMaterial,
+    // >::default())
+    .insert_resource(BoidsSeed::from_args())
+    .add_startup_system(log_seed_system)
+    .add_startup_system(config::load_boids_config_system)
+    // The flock is respawned in the same stage, so by the time it steps in
+    // `Update` the boids match the species of the new config
+    .add_system_to_stage(CoreStage::PreUpdate, config::apply_boids_config_system);
 
-        // boids_partition.insert(boids.positions[boid.id], boid.id);
+    if let Some(path) = arg_value("--replay") {
+        if let Err(err) = recording::add_replay(&mut app, &path) {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+    } else if std::env::args().any(|arg| arg == "--3d") {
+        add_3d(&mut app);
+    } else {
+        add_2d(&mut app);
+    }
 
-        // let root = &*boids_partition;
+    app.run();
+}
 
-        // let nw = &root.children.as_ref().unwrap()[0];
-        // let ne = &root.children.as_ref().unwrap()[1];
-        // let sw = &root.children.as_ref().unwrap()[2];
-        // let se = &root.children.as_ref().unwrap()[3];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spatial_partition::{Octree, QuadTree};
+    use std::time::{Duration, Instant};
+    use sweep::{run_flock, spawn_flock};
 
-        // let bounds = &root.boundary;
+    /// Positions after every fixed step.
+    #[derive(Resource, Default)]
+    struct StepHistory(Vec<Vec<Vec2>>);
 
-        // let square = shapes::RegularPolygon {
-        //     sides: 4,
-        //     center: Vec2::new(0.0, 0.0),
-        //     feature: shapes::RegularPolygonFeature::SideLength(200.0),
-        // };
+    /// Interpolated positions at the end of every frame, with the time of the frame.
+    #[derive(Resource, Default)]
+    struct FrameHistory(Vec<(Duration, Vec<Vec2>)>);
 
-        // commands
-        // .spawn(square.draw(
-        //     materials.add(ColorMaterial::color(Color::rgba_linear(0.0, 1.0, 0.0, 0.5))),
-        //     TessellationMode::Fill(FillOptions::default()),
-        //     Transform {
-        //         translation: Vec3::new(0.0, 0.0, 0.0),
-        //         ..Default::default()
-        //     },
-        // ))
-        // .with(Square);
+    fn record_step_system(boids: Query<&Position, With<Boid>>, mut history: ResMut<StepHistory>) {
+        history
+            .0
+            .push(boids.iter().map(|position| position.0).collect());
     }
-}
 
-fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
-        // .add_plugin(ShapePlugin)
-        // // Adds frame time diagnostics
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
-        // // Adds a system that prints diagnostics to the console
-        //        .add_plugin(PrintDiagnosticsPlugin::default())
-        // // Any plugin can register diagnostics
-        // // Uncomment this to add some render resource diagnostics:
-        // .add_plugin(bevy::wgpu::diagnostic::WgpuResourceDiagnosticsPlugin::default())
-        // // Uncomment this to add an entity count diagnostics:
-        // .add_plugin(bevy::diagnostic::EntityCountDiagnosticsPlugin::default())
-        // // Uncomment this to add an asset count diagnostics:
-        // .add_plugin(bevy::asset::diagnostic::AssetCountDiagnosticsPlugin::<
-        //     ColorMaterial,
-        // >::default())
-        // .add_resource(QuadTree::new(AABB::new(Vec2::zero(), Vec2::new(800.0, 800.0))) as QuadTree)
-        .add_startup_system(setup)
-        .add_system(boids_system)
-        .run();
+    fn record_frame_system(
+        time: Res<Time>,
+        boids: Query<(&PreviousPosition, &Position)>,
+        fixed_timesteps: Res<FixedTimesteps>,
+        mut history: ResMut<FrameHistory>,
+    ) {
+        let alpha = fixed_timesteps
+            .get(TIMESTEP_LABEL)
+            .map_or(1.0, |state| state.overstep_percentage() as f32);
+        history.0.push((
+            time.elapsed(),
+            boids
+                .iter()
+                .map(|(previous, position)| previous.0.lerp(position.0, alpha))
+                .collect(),
+        ));
+    }
+
+    fn test_flock() -> Vec<BoidBundle> {
+        (0..40)
+            .map(|i| {
+                let angle = i as f32 * 0.7;
+                let position = Vec2::new(angle.cos(), angle.sin()) * (20.0 + i as f32 * 4.0);
+                let velocity = Vec2::new(angle.sin(), -angle.cos()) * 100.0;
+                BoidBundle::new(position, velocity, 0)
+            })
+            .collect()
+    }
+
+    /// Runs the simulation without a window for `seconds`, rendering at `frame_rate`.
+    fn run_headless(frame_rate: f64, seconds: f64) -> (StepHistory, FrameHistory) {
+        let world_bounds = AABB::new(Vec2::ZERO, Vec2::new(320.0, 240.0));
+        let partition = BoidsPartition(Box::new(QuadTree::new(world_bounds)));
+
+        let start = Instant::now();
+        let mut app = App::new();
+        spawn_flock(&mut app.world, test_flock(), partition);
+        app.insert_resource(Time::new(start))
+            .init_resource::<FixedTimesteps>()
+            .init_resource::<BoidsConfig>()
+            .init_resource::<FlockSteering>()
+            .init_resource::<StepHistory>()
+            .init_resource::<FrameHistory>()
+            .insert_resource(WorldBounds::new(world_bounds, Edges::Wrap))
+            .add_system_set(
+                simulation_systems::<Vec2>()
+                    .with_system(record_step_system.after(move_boids_system::<Vec2>)),
+            )
+            .add_system(record_frame_system.after(move_boids_system::<Vec2>));
+
+        let frames = (seconds * frame_rate).round() as u32;
+        for frame in 0..=frames {
+            let elapsed = Duration::from_secs_f64(frame as f64 / frame_rate);
+            app.world
+                .resource_mut::<Time>()
+                .update_with_instant(start + elapsed);
+            app.update();
+        }
+
+        let steps = app.world.remove_resource::<StepHistory>().unwrap();
+        let frames = app.world.remove_resource::<FrameHistory>().unwrap();
+        (steps, frames)
+    }
+
+    fn assert_close(a: &[Vec2], b: &[Vec2]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!(a.distance(*b) < 1e-2, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn frame_rate_independent_trajectories() {
+        let (steps_60, frames_60) = run_headless(60.0, 3.0);
+        let (steps_144, frames_144) = run_headless(144.0, 3.0);
+
+        // Both ran the same fixed steps, give or take the one straddling the last frame
+        assert!(steps_60.0.len() >= 179);
+        assert!((steps_60.0.len() as i64 - steps_144.0.len() as i64).abs() <= 1);
+        for (a, b) in steps_60.0.iter().zip(steps_144.0.iter()) {
+            assert_close(a, b);
+        }
+
+        // Frames rendered at the same instant show the flock at the same place
+        let mut compared = 0;
+        for (time, positions) in &frames_60.0 {
+            if let Some((_, other)) = frames_144
+                .0
+                .iter()
+                .find(|(other_time, _)| other_time.abs_diff(*time) < Duration::from_micros(1))
+            {
+                assert_close(positions, other);
+                compared += 1;
+            }
+        }
+        assert!(compared > 30);
+    }
+
+    #[test]
+    fn boids_move_the_same_distance_per_second() {
+        let (_, frames) = run_headless(30.0, 1.0);
+        let (_, other_frames) = run_headless(120.0, 1.0);
+
+        assert_close(
+            &frames.0.last().unwrap().1,
+            &other_frames.0.last().unwrap().1,
+        );
+    }
+
+    const SNAPSHOT_SEED: u64 = 42;
+    const SNAPSHOT_BOIDS: usize = 50;
+    const SNAPSHOT_TICKS: usize = 120;
+    const SNAPSHOT_PATH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/bin/boids/snapshots/flock_seed_42.csv"
+    );
+
+    fn single_species(count: usize) -> BoidsConfig {
+        BoidsConfig {
+            species: vec![config::Species {
+                count,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Where the boids ended up and how fast they go, in the order they were
+    /// spawned.
+    struct Flock<V: Vector = Vec2> {
+        positions: Vec<V>,
+        velocities: Vec<V>,
+    }
+
+    /// Steps `flock` `ticks` times without a window, in `world_bounds` indexed by
+    /// `partition`.
+    fn run_in<V: Vector>(
+        flock: Vec<BoidBundle<V>>,
+        config: &BoidsConfig,
+        partition: BoidsPartition<V>,
+        world_bounds: WorldBounds<V>,
+        ticks: usize,
+    ) -> Flock<V> {
+        let mut world = run_flock(flock, config, partition, world_bounds, ticks);
+        let (positions, velocities) = world
+            .query::<(&Position<V>, &Velocity<V>)>()
+            .iter(&world)
+            .map(|(position, velocity)| (position.0, velocity.0))
+            .unzip();
+        Flock {
+            positions,
+            velocities,
+        }
+    }
+
+    /// Steps `flock` `ticks` times without a window.
+    fn run(flock: Vec<BoidBundle>, config: &BoidsConfig, ticks: usize) -> Flock {
+        // Large enough to hold the whole spawn disc
+        let world_bounds = AABB::new(Vec2::ZERO, Vec2::new(640.0, 640.0));
+        let partition = BoidsPartition(Box::new(QuadTree::new(world_bounds)));
+        run_in(
+            flock,
+            config,
+            partition,
+            WorldBounds::new(world_bounds, Edges::Wrap),
+            ticks,
+        )
+    }
+
+    /// Sets up a flock from `seed` and steps it `ticks` times without a window.
+    fn run_seeded(seed: u64, count: usize, ticks: usize) -> Flock {
+        let config = single_species(count);
+        let mut rng = StdRng::seed_from_u64(seed);
+        run(boids::random_flock(&mut rng, &config), &config, ticks)
+    }
+
+    fn parse_snapshot(snapshot: &str) -> Vec<Vec2> {
+        snapshot
+            .lines()
+            .map(|line| {
+                let (x, y) = line.split_once(',').unwrap();
+                Vec2::new(x.parse().unwrap(), y.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_flock() {
+        let a = run_seeded(7, 30, 60);
+        let b = run_seeded(7, 30, 60);
+
+        assert_eq!(a.positions, b.positions);
+        assert_eq!(a.velocities, b.velocities);
+    }
+
+    #[test]
+    fn different_seeds_different_flocks() {
+        let a = run_seeded(7, 30, 0);
+        let b = run_seeded(8, 30, 0);
+
+        assert_ne!(a.positions, b.positions);
+    }
+
+    /// Compares a seeded run against the positions stored in `snapshots/`. Run
+    /// with `UPDATE_SNAPSHOTS=1` to rewrite the file after an intended change.
+    #[test]
+    fn seeded_flock_matches_snapshot() {
+        let boids = run_seeded(SNAPSHOT_SEED, SNAPSHOT_BOIDS, SNAPSHOT_TICKS);
+
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            let snapshot = boids
+                .positions
+                .iter()
+                .map(|position| format!("{},{}\n", position.x, position.y))
+                .collect::<String>();
+            std::fs::write(SNAPSHOT_PATH, snapshot).unwrap();
+        }
+
+        let snapshot = std::fs::read_to_string(SNAPSHOT_PATH).unwrap();
+        let expected = parse_snapshot(&snapshot);
+
+        assert_eq!(boids.positions.len(), expected.len());
+        for (id, (position, expected)) in boids.positions.iter().zip(&expected).enumerate() {
+            assert!(
+                position.distance(*expected) < 1e-3,
+                "boid {} is at {}, snapshot has {}",
+                id,
+                position,
+                expected
+            );
+        }
+    }
+
+    fn species(name: &str) -> config::Species {
+        config::Species {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn interaction(first: &str, second: &str, relation: config::Relation) -> config::Interaction {
+        config::Interaction {
+            between: (first.to_string(), second.to_string()),
+            relation,
+        }
+    }
+
+    /// What the metrics are for: telling whether a rule makes a difference.
+    #[test]
+    fn alignment_shows_in_the_polarization() {
+        let aligned = single_species(100);
+        let mut unaligned = aligned.clone();
+        unaligned.species[0].flocking.alignment_weight = 0.0;
+
+        let polarization = |config: &BoidsConfig| {
+            let flock = boids::random_flock(&mut StdRng::seed_from_u64(3), config);
+            let flock = run(flock, config, 600);
+            analysis::polarization(flock.velocities.into_iter())
+        };
+        let (aligned, unaligned) = (polarization(&aligned), polarization(&unaligned));
+        assert!(aligned > unaligned * 2.0, "{} vs {}", aligned, unaligned);
+    }
+
+    #[test]
+    fn bounded_flocks_stay_inside() {
+        let config = single_species(0);
+        let world_bounds = AABB::new(Vec2::ZERO, Vec2::splat(200.0));
+
+        for edges in [Edges::Reflect, Edges::Steer] {
+            let flock = run_in(
+                test_flock(),
+                &config,
+                BoidsPartition(Box::new(QuadTree::new(world_bounds))),
+                WorldBounds::new(world_bounds, edges),
+                300,
+            );
+            for position in flock.positions {
+                assert!(world_bounds.contains(position), "{:?}: {}", edges, position);
+            }
+        }
+    }
+
+    #[test]
+    fn ignored_species_dont_disturb_each_other() {
+        let alone = single_species(30);
+        let mut rng = StdRng::seed_from_u64(3);
+        let flock = boids::random_flock(&mut rng, &alone);
+
+        // The same flock with a second species on top of it
+        let config = BoidsConfig {
+            species: vec![species("sparrow"), species("crow")],
+            interactions: vec![interaction("sparrow", "crow", config::Relation::Ignore)],
+        };
+        let mixed = (0..2)
+            .flat_map(|species| {
+                flock.iter().map(move |boid| BoidBundle {
+                    species: SpeciesId(species),
+                    ..boid.clone()
+                })
+            })
+            .collect();
+
+        let alone = run(flock, &alone, 30);
+        let mixed = run(mixed, &config, 30);
+
+        assert_eq!(alone.positions[..], mixed.positions[..30]);
+        assert_eq!(alone.positions[..], mixed.positions[30..]);
+    }
+
+    #[test]
+    fn predators_chase_prey() {
+        let config = BoidsConfig {
+            species: vec![species("sparrow"), species("hawk")],
+            interactions: vec![interaction("hawk", "sparrow", config::Relation::Hunts)],
+        };
+        let boids = vec![
+            BoidBundle::new(Vec2::new(50.0, 0.0), Vec2::ZERO, 0),
+            BoidBundle::new(Vec2::ZERO, Vec2::ZERO, 1),
+        ];
+
+        let boids = run(boids, &config, 1);
+
+        assert!(boids.velocities[0].x > 0.0);
+        assert!(boids.velocities[1].x > 0.0);
+        assert_eq!(boids.velocities[0].y, 0.0);
+        assert_eq!(boids.velocities[1].y, 0.0);
+    }
+
+    #[test]
+    fn flat_3d_flock_follows_the_2d_one() {
+        let config = single_species(40);
+        let mut rng = StdRng::seed_from_u64(5);
+        let flat = boids::random_flock::<Vec2>(&mut rng, &config);
+
+        // The same flock in 3D, lying in the z = 0 plane
+        let deep = flat
+            .iter()
+            .map(|boid| {
+                BoidBundle::new(
+                    boid.position.0.extend(0.0),
+                    boid.velocity.0.extend(0.0),
+                    boid.species.0,
+                )
+            })
+            .collect();
+        let world_bounds = AABB::new(Vec3::ZERO, Vec3::splat(640.0));
+        let deep = run_in(
+            deep,
+            &config,
+            BoidsPartition(Box::new(Octree::new(world_bounds))),
+            WorldBounds::new(world_bounds, Edges::Wrap),
+            30,
+        );
+
+        let flat = run(flat, &config, 30);
+        for (flat, deep) in flat.positions.iter().zip(&deep.positions) {
+            assert_eq!(deep.z, 0.0);
+            assert!(
+                flat.distance(deep.truncate()) < 1e-3,
+                "{} != {}",
+                flat,
+                deep
+            );
+        }
+    }
+
+    /// `boids_system` steering one boid after the other.
+    fn serial_boids_system(
+        boids_partition: Res<BoidsPartition>,
+        world_bounds: Res<WorldBounds>,
+        steering: Res<FlockSteering>,
+        config: Res<BoidsConfig>,
+        neighbors: Neighbors<Vec2>,
+        mut boids: Query<(Entity, &Position, &Velocity, &SpeciesId, &mut Acceleration)>,
+    ) {
+        let surroundings = boids::Surroundings::default();
+        let step = FlockStep::new(
+            &neighbors,
+            boids_partition.0.as_ref(),
+            &world_bounds,
+            &surroundings,
+            &steering,
+            &config,
+        );
+
+        for (entity, position, velocity, species, mut acceleration) in boids.iter_mut() {
+            acceleration.0 = step.steer(entity, position.0, velocity.0, species.0);
+        }
+    }
+
+    #[test]
+    fn parallel_steering_matches_serial() {
+        let config = BoidsConfig {
+            species: vec![
+                config::Species {
+                    count: 1500,
+                    ..species("sparrow")
+                },
+                config::Species {
+                    count: 20,
+                    ..species("hawk")
+                },
+            ],
+            interactions: vec![interaction("hawk", "sparrow", config::Relation::Hunts)],
+        };
+        let mut rng = StdRng::seed_from_u64(11);
+        let world_bounds = AABB::new(Vec2::ZERO, Vec2::new(640.0, 640.0));
+
+        let mut world = World::new();
+        world.insert_resource(WorldBounds::new(world_bounds, Edges::Wrap));
+        world.insert_resource(FlockSteering::<Vec2>::from_config(&config));
+        spawn_flock(
+            &mut world,
+            boids::random_flock(&mut rng, &config),
+            BoidsPartition(Box::new(QuadTree::new(world_bounds))),
+        );
+        world.insert_resource(config);
+
+        let mut accelerations = |system| {
+            SystemStage::single_threaded()
+                .with_system(system)
+                .run(&mut world);
+            world
+                .query::<&Acceleration>()
+                .iter(&world)
+                .map(|acceleration| acceleration.0)
+                .collect::<Vec<_>>()
+        };
+
+        let parallel = accelerations(boids_system::<Vec2>.into_descriptor());
+        let serial = accelerations(serial_boids_system.into_descriptor());
+        assert!(parallel
+            .iter()
+            .any(|acceleration| *acceleration != Vec2::ZERO));
+        assert_eq!(parallel, serial);
+    }
+
+    #[test]
+    fn grid_follows_the_loaded_neighbourhood() {
+        let mut config = BoidsConfig::default();
+        let mut rng = StdRng::seed_from_u64(3);
+        let world_bounds = AABB::new(Vec2::ZERO, Vec2::new(640.0, 640.0));
+
+        let mut world = World::new();
+        world.insert_resource(WorldBounds::new(world_bounds, Edges::Wrap));
+        world.insert_resource(Index::Grid);
+        spawn_flock(
+            &mut world,
+            boids::random_flock(&mut rng, &config),
+            BoidsPartition(Box::new(QuadTree::new(world_bounds))),
+        );
+        let count = config.species[0].count;
+        world.insert_resource(config.clone());
+        let mut stage = SystemStage::single_threaded().with_system(resize_grid_system::<Vec2>);
+
+        let mut cell_sizes = |world: &mut World| {
+            stage.run(world);
+            let partition = world.resource::<BoidsPartition>();
+            assert_eq!(partition.len(), count);
+            partition
+                .cells()
+                .iter()
+                .map(|cell| cell.half_dimension.x * 2.0)
+                .collect::<Vec<_>>()
+        };
+        let radius = config.neighbor_radius();
+        assert!(cell_sizes(&mut world).iter().all(|size| *size == radius));
+
+        config.species[0].flocking.cohesion_radius = radius * 2.0;
+        world.insert_resource(config.clone());
+        assert!(cell_sizes(&mut world)
+            .iter()
+            .all(|size| *size == radius * 2.0));
+    }
 }
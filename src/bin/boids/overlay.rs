@@ -0,0 +1,213 @@
+//! Debug overlay of the 2D flock, shown and hidden with `D`: the cells of the
+//! spatial partition, the velocity of every boid, the neighbourhood of the boid
+//! under the cursor, and a readout of the flock size, tree depth and frame rate,
+//! along with the flock metrics.
+
+use super::*;
+use bevy::diagnostic::Diagnostics;
+
+const TOGGLE_KEY: KeyCode = KeyCode::D;
+
+/// Seconds of travel drawn by the velocity lines.
+const VELOCITY_SCALE: f32 = 0.1;
+
+#[derive(Resource, Default)]
+pub struct DebugOverlay {
+    pub enabled: bool,
+}
+
+/// What an overlay path entity draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+enum OverlayLayer {
+    Cells,
+    Velocities,
+    Neighbors,
+}
+
+#[derive(Component)]
+struct Readout;
+
+/// Anything shown and hidden with the overlay.
+#[derive(Component)]
+struct Overlay;
+
+pub fn add_overlay(app: &mut App) {
+    app.init_resource::<DebugOverlay>()
+        .add_startup_system(spawn_overlay_system)
+        .add_system(toggle_overlay_system)
+        .add_system(
+            draw_overlay_system
+                .after(toggle_overlay_system)
+                .after(boid_sprites_system::<Vec2>),
+        )
+        .add_system(readout_system.after(toggle_overlay_system));
+}
+
+fn spawn_overlay_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let hidden = Visibility { is_visible: false };
+
+    for (layer, color, z) in [
+        (OverlayLayer::Cells, Color::rgba(0.0, 1.0, 0.0, 0.4), 1.0),
+        (
+            OverlayLayer::Velocities,
+            Color::rgba(1.0, 1.0, 1.0, 0.6),
+            2.0,
+        ),
+        (OverlayLayer::Neighbors, Color::YELLOW, 3.0),
+    ] {
+        let mut shape = GeometryBuilder::build_as(
+            &PathBuilder::new().build(),
+            DrawMode::Stroke(StrokeMode::new(color, 1.0)),
+            Transform::from_translation(Vec3::new(0.0, 0.0, z)),
+        );
+        shape.visibility = hidden;
+        commands.spawn((shape, layer, Overlay));
+    }
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+    let mut readout = TextBundle::from_section("", style).with_style(Style {
+        position_type: PositionType::Absolute,
+        position: UiRect {
+            top: Val::Px(5.0),
+            left: Val::Px(5.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    readout.visibility = hidden;
+    commands.spawn((readout, Readout, Overlay));
+}
+
+fn toggle_overlay_system(
+    keys: Res<Input<KeyCode>>,
+    mut overlay: ResMut<DebugOverlay>,
+    mut visibilities: Query<&mut Visibility, With<Overlay>>,
+) {
+    if !keys.just_pressed(TOGGLE_KEY) {
+        return;
+    }
+
+    overlay.enabled = !overlay.enabled;
+    for mut visibility in visibilities.iter_mut() {
+        visibility.is_visible = overlay.enabled;
+    }
+}
+
+/// Outline of every cell.
+fn cells_path(cells: &[AABB]) -> Path {
+    let mut path = PathBuilder::new();
+    for cell in cells {
+        let (min, max) = (cell.min(), cell.max());
+        path.move_to(min);
+        path.line_to(Vec2::new(max.x, min.y));
+        path.line_to(max);
+        path.line_to(Vec2::new(min.x, max.y));
+        path.close();
+    }
+    path.build()
+}
+
+/// One line per boid along its velocity.
+fn velocities_path(boids: &[(Vec2, Vec2)]) -> Path {
+    let mut path = PathBuilder::new();
+    for (position, velocity) in boids {
+        path.move_to(*position);
+        path.line_to(*position + *velocity * VELOCITY_SCALE);
+    }
+    path.build()
+}
+
+/// Circle of radius `radius` around `center` and a line to each neighbour.
+fn neighbors_path(center: Vec2, radius: f32, neighbors: &[Vec2]) -> Path {
+    let mut path = ShapePath::new().add(&shapes::Circle { radius, center });
+    for neighbor in neighbors {
+        path = path.add(&shapes::Line(center, *neighbor));
+    }
+    path.build()
+}
+
+/// Redraws the overlay from the latest step while it is shown.
+fn draw_overlay_system(
+    overlay: Res<DebugOverlay>,
+    boids_partition: Res<BoidsPartition>,
+    world_bounds: Res<WorldBounds>,
+    config: Res<BoidsConfig>,
+    windows: Res<Windows>,
+    boids: Query<(&Position, &Velocity, &SpeciesId), With<Boid>>,
+    mut layers: Query<(&mut Path, &OverlayLayer)>,
+) {
+    if !overlay.enabled {
+        return;
+    }
+
+    // The boid closest to the cursor shows who it reacts to
+    let selected = cursor_position(&windows)
+        .and_then(|cursor| boids_partition.k_nearest(cursor, 1).into_iter().next())
+        .and_then(|(position, entity)| Some((position, entity, boids.get(entity).ok()?.2)));
+
+    for (mut path, layer) in layers.iter_mut() {
+        *path = match layer {
+            OverlayLayer::Cells => cells_path(&boids_partition.cells()),
+            OverlayLayer::Velocities => velocities_path(
+                &boids
+                    .iter()
+                    .map(|(position, velocity, _)| (position.0, velocity.0))
+                    .collect::<Vec<_>>(),
+            ),
+            OverlayLayer::Neighbors => match selected {
+                Some((position, entity, species)) => {
+                    let radius = config.species[species.0].flocking.neighbor_radius();
+                    let neighbors = world_bounds
+                        .query_radius(boids_partition.0.as_ref(), position, radius)
+                        .into_iter()
+                        .filter(|(_, other)| *other != entity)
+                        .map(|(neighbor, _)| neighbor)
+                        .collect::<Vec<_>>();
+                    neighbors_path(position, radius, &neighbors)
+                }
+                None => PathBuilder::new().build(),
+            },
+        };
+    }
+}
+
+fn readout_system(
+    overlay: Res<DebugOverlay>,
+    diagnostics: Res<Diagnostics>,
+    boids_partition: Res<BoidsPartition>,
+    boids: Query<(), With<Boid>>,
+    mut readouts: Query<&mut Text, With<Readout>>,
+) {
+    if !overlay.enabled {
+        return;
+    }
+
+    let fps = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.smoothed())
+        .unwrap_or(0.0);
+
+    let mut readout = format!(
+        "boids: {}\ndepth: {}\nfps:   {:.0}",
+        boids.iter().count(),
+        boids_partition.depth(),
+        fps
+    );
+    for (name, id) in [
+        ("polarization", analysis::POLARIZATION),
+        ("nearest", analysis::MEAN_NEAREST_DISTANCE),
+        ("clusters", analysis::CLUSTERS),
+    ] {
+        if let Some(value) = diagnostics.get(id).and_then(|metric| metric.value()) {
+            readout += &format!("\n{}: {:.2}", name, value);
+        }
+    }
+
+    for mut text in readouts.iter_mut() {
+        text.sections[0].value = readout.clone();
+    }
+}
@@ -0,0 +1,463 @@
+//! Recording runs to CSV and playing them back.
+//!
+//! A recording has one line per boid and step, `tick,species,x,y,vx,vy` in 2D
+//! and with `z` and `vz` as well in 3D, under a header naming the columns.
+
+use super::*;
+use anyhow::{bail, Context};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+const AXES: [&str; 3] = ["x", "y", "z"];
+
+/// Ticks skipped by one press of an arrow key with shift held.
+const SCRUB_JUMP: usize = 10;
+
+/// Longest recording read back, a few hours at 60 steps a second. Ticks without
+/// boids have no lines, so without a limit one line could ask for any length.
+const MAX_TICKS: usize = 1 << 20;
+
+/// State of one boid at a recorded tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedBoid<V: Vector = Vec2> {
+    pub species: usize,
+    pub position: V,
+    pub velocity: V,
+}
+
+fn header<V: Vector>() -> String {
+    let axes = &AXES[..V::DIM];
+    let velocities = axes.iter().map(|axis| format!("v{}", axis));
+    ["tick", "species"]
+        .into_iter()
+        .map(String::from)
+        .chain(axes.iter().map(|axis| axis.to_string()))
+        .chain(velocities)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Writes the lines of the boids at `tick`.
+pub fn write_tick<V: Vector>(
+    out: &mut impl Write,
+    tick: usize,
+    boids: impl IntoIterator<Item = RecordedBoid<V>>,
+) -> io::Result<()> {
+    for boid in boids {
+        write!(out, "{},{}", tick, boid.species)?;
+        for vector in [boid.position, boid.velocity] {
+            for axis in 0..V::DIM {
+                write!(out, ",{}", vector.component(axis))?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes every step of the flock to a file, with `--record <path>` on the
+/// command line.
+#[derive(Resource)]
+pub struct Recorder {
+    out: BufWriter<File>,
+    tick: usize,
+}
+
+impl Recorder {
+    pub fn create<V: Vector>(path: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", header::<V>())?;
+        Ok(Self { out, tick: 0 })
+    }
+}
+
+/// Records the flock once it moved. Every step is flushed, so the file is
+/// complete however the app is closed.
+#[allow(clippy::type_complexity)]
+pub fn record_system<V: Vector>(
+    mut recorder: ResMut<Recorder>,
+    boids: Query<(&SpeciesId, &Position<V>, &Velocity<V>), With<Boid>>,
+) {
+    let recorder = &mut *recorder;
+    let boids = boids
+        .iter()
+        .map(|(species, position, velocity)| RecordedBoid {
+            species: species.0,
+            position: position.0,
+            velocity: velocity.0,
+        });
+
+    if let Err(err) =
+        write_tick(&mut recorder.out, recorder.tick, boids).and_then(|_| recorder.out.flush())
+    {
+        error!("Failed to record tick {}: {}", recorder.tick, err);
+    }
+    recorder.tick += 1;
+}
+
+/// A recorded run, read back.
+#[derive(Resource, Debug, Default)]
+pub struct Recording<V: Vector = Vec2> {
+    ticks: Vec<Vec<RecordedBoid<V>>>,
+}
+
+impl<V: Vector> Recording<V> {
+    pub fn parse(csv: &str) -> anyhow::Result<Self> {
+        let mut lines = csv.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) if line == header::<V>() => {}
+            Some((_, line)) => bail!(
+                "expected a {}D recording starting with \"{}\", found \"{}\"",
+                V::DIM,
+                header::<V>(),
+                line
+            ),
+            None => bail!("empty recording"),
+        }
+
+        let mut recording = Self::default();
+        for (number, line) in lines {
+            let (tick, boid) = parse_line(line).with_context(|| format!("line {}", number + 1))?;
+            if tick >= MAX_TICKS {
+                bail!(
+                    "line {}: tick {} is past the longest recording, {} ticks",
+                    number + 1,
+                    tick,
+                    MAX_TICKS
+                );
+            }
+            if tick + 1 < recording.ticks.len() {
+                bail!("line {}: tick {} is out of order", number + 1, tick);
+            }
+            // Ticks without boids have no lines
+            recording.ticks.resize_with(tick + 1, Vec::new);
+            recording.ticks[tick].push(boid);
+        }
+
+        Ok(recording)
+    }
+
+    /// Number of recorded ticks.
+    pub fn len(&self) -> usize {
+        self.ticks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ticks.is_empty()
+    }
+
+    pub fn tick(&self, tick: usize) -> &[RecordedBoid<V>] {
+        &self.ticks[tick]
+    }
+}
+
+fn parse_line<V: Vector>(line: &str) -> anyhow::Result<(usize, RecordedBoid<V>)> {
+    let fields = line.split(',').collect::<Vec<_>>();
+    if fields.len() != 2 + 2 * V::DIM {
+        bail!("expected {} fields, found {}", 2 + 2 * V::DIM, fields.len());
+    }
+
+    let vector = |fields: &[&str]| -> anyhow::Result<V> {
+        let mut vector = V::ZERO;
+        for (axis, field) in fields.iter().enumerate() {
+            let component = field
+                .parse::<f32>()
+                .with_context(|| format!("invalid number \"{}\"", field))?;
+            vector += V::axis(axis) * component;
+        }
+        Ok(vector)
+    };
+
+    let tick = fields[0]
+        .parse()
+        .with_context(|| format!("invalid tick \"{}\"", fields[0]))?;
+    let boid = RecordedBoid {
+        species: fields[1]
+            .parse()
+            .with_context(|| format!("invalid species \"{}\"", fields[1]))?,
+        position: vector(&fields[2..2 + V::DIM])?,
+        velocity: vector(&fields[2 + V::DIM..])?,
+    };
+    Ok((tick, boid))
+}
+
+/// Where the replay is at.
+#[derive(Resource, Debug, Default)]
+pub struct Playback {
+    pub tick: usize,
+    pub paused: bool,
+}
+
+/// Entity showing a boid of the recording, of the species it was spawned for.
+#[derive(Component)]
+struct ReplayBoid;
+
+/// Plays the recording at `path` back instead of simulating, with
+/// `--replay <path>` on the command line, in 2D or in 3D as it was recorded.
+/// Space pauses, the arrow keys scrub through it one tick at a time, or
+/// `SCRUB_JUMP` with shift, and home and end go to the start and the end.
+pub fn add_replay(app: &mut App, path: &str) -> anyhow::Result<()> {
+    let csv = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let context = || format!("Failed to parse {}", path);
+
+    if csv.lines().next() == Some(header::<Vec3>().as_str()) {
+        add_playback(app, Recording::<Vec3>::parse(&csv).with_context(context)?);
+        app.add_startup_system(setup_3d)
+            .add_system(replay_spheres_system.after(playback_controls_system::<Vec3>));
+    } else {
+        add_playback(app, Recording::<Vec2>::parse(&csv).with_context(context)?);
+        app.add_plugin(ShapePlugin)
+            .add_startup_system(setup)
+            .add_startup_system(spawn_obstacles_system)
+            .add_system(replay_sprites_system.after(playback_controls_system::<Vec2>));
+    }
+    Ok(())
+}
+
+fn add_playback<V: Vector>(app: &mut App, recording: Recording<V>) {
+    app.insert_resource(recording)
+        .init_resource::<Playback>()
+        .add_system(playback_system::<V>.with_run_criteria(FixedTimestep::step(TIMESTEP)))
+        .add_system(playback_controls_system::<V>.after(playback_system::<V>))
+        .add_system(replay_title_system::<V>.after(playback_controls_system::<V>));
+}
+
+/// Moves the replay on by one tick per simulation step, stopping at the end.
+fn playback_system<V: Vector>(recording: Res<Recording<V>>, mut playback: ResMut<Playback>) {
+    if !playback.paused && playback.tick + 1 < recording.len() {
+        playback.tick += 1;
+    }
+}
+
+fn playback_controls_system<V: Vector>(
+    keys: Res<Input<KeyCode>>,
+    recording: Res<Recording<V>>,
+    mut playback: ResMut<Playback>,
+) {
+    let last = recording.len().saturating_sub(1);
+    let step = if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        SCRUB_JUMP
+    } else {
+        1
+    };
+
+    if keys.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if keys.just_pressed(KeyCode::Left) {
+        playback.tick = playback.tick.saturating_sub(step);
+    }
+    if keys.just_pressed(KeyCode::Right) {
+        playback.tick = (playback.tick + step).min(last);
+    }
+    if keys.just_pressed(KeyCode::Home) {
+        playback.tick = 0;
+    }
+    if keys.just_pressed(KeyCode::End) {
+        playback.tick = last;
+    }
+}
+
+fn replay_title_system<V: Vector>(
+    recording: Res<Recording<V>>,
+    playback: Res<Playback>,
+    mut windows: ResMut<Windows>,
+) {
+    if !playback.is_changed() || recording.is_empty() {
+        return;
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_title(format!(
+            "boids replay {}/{}{}",
+            playback.tick,
+            recording.len() - 1,
+            if playback.paused { " (paused)" } else { "" }
+        ));
+    }
+}
+
+/// Keeps one entity per boid of the current tick, along with the species it
+/// shows. Entities are moved to the boids, and replaced with `spawn` when the
+/// species differs: the flock may have been edited while it was recorded.
+/// Every entity is replaced when `recolor` is set.
+fn show_tick<V: Vector>(
+    commands: &mut Commands,
+    boids: &[RecordedBoid<V>],
+    spawned: &mut Vec<(Entity, usize)>,
+    transforms: &mut Query<&mut Transform, With<ReplayBoid>>,
+    recolor: bool,
+    mut spawn: impl FnMut(&mut Commands, &RecordedBoid<V>) -> Entity,
+) {
+    if recolor {
+        for (entity, _) in spawned.drain(..) {
+            commands.entity(entity).despawn();
+        }
+    }
+    for (index, boid) in boids.iter().enumerate() {
+        match spawned.get(index) {
+            Some(&(entity, species)) if species == boid.species => {
+                if let Ok(mut transform) = transforms.get_mut(entity) {
+                    transform.translation = boid.position.to_translation();
+                }
+            }
+            Some(&(entity, _)) => {
+                commands.entity(entity).despawn();
+                spawned[index] = (spawn(commands, boid), boid.species);
+            }
+            None => spawned.push((spawn(commands, boid), boid.species)),
+        }
+    }
+    for (entity, _) in spawned.drain(boids.len().min(spawned.len())..) {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn species_color(config: &BoidsConfig, species: usize) -> [f32; 3] {
+    config.species.get(species).map_or([1.0; 3], |s| s.color)
+}
+
+/// Shows the boids of a 2D recording as sprites.
+fn replay_sprites_system(
+    mut commands: Commands,
+    recording: Res<Recording>,
+    playback: Res<Playback>,
+    config: Res<BoidsConfig>,
+    mut transforms: Query<&mut Transform, With<ReplayBoid>>,
+    mut spawned: Local<Vec<(Entity, usize)>>,
+) {
+    if !(playback.is_changed() || config.is_changed()) || recording.is_empty() {
+        return;
+    }
+
+    show_tick(
+        &mut commands,
+        recording.tick(playback.tick),
+        &mut spawned,
+        &mut transforms,
+        config.is_changed(),
+        |commands, boid| {
+            let sprite = boid_sprite(species_color(&config, boid.species), boid.position);
+            commands.spawn((sprite, ReplayBoid)).id()
+        },
+    );
+}
+
+/// Shows the boids of a 3D recording as small spheres, like the simulation.
+#[allow(clippy::too_many_arguments)]
+fn replay_spheres_system(
+    mut commands: Commands,
+    recording: Res<Recording<Vec3>>,
+    playback: Res<Playback>,
+    config: Res<BoidsConfig>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut transforms: Query<&mut Transform, With<ReplayBoid>>,
+    mut spawned: Local<Vec<(Entity, usize)>>,
+    mut mesh: Local<Option<Handle<Mesh>>>,
+    mut species_materials: Local<HashMap<usize, Handle<StandardMaterial>>>,
+) {
+    if !(playback.is_changed() || config.is_changed()) || recording.is_empty() {
+        return;
+    }
+    if config.is_changed() {
+        species_materials.clear();
+    }
+
+    let mesh = mesh
+        .get_or_insert_with(|| {
+            meshes.add(Mesh::from(shape::Icosphere {
+                radius: 3.0,
+                subdivisions: 1,
+            }))
+        })
+        .clone();
+    show_tick(
+        &mut commands,
+        recording.tick(playback.tick),
+        &mut spawned,
+        &mut transforms,
+        config.is_changed(),
+        |commands, boid| {
+            let material = species_materials
+                .entry(boid.species)
+                .or_insert_with(|| {
+                    let [r, g, b] = species_color(&config, boid.species);
+                    materials.add(Color::rgb(r, g, b).into())
+                })
+                .clone();
+            let pbr = PbrBundle {
+                mesh: mesh.clone(),
+                material,
+                transform: Transform::from_translation(boid.position),
+                ..Default::default()
+            };
+            commands.spawn((pbr, ReplayBoid)).id()
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boid<V: Vector>(species: usize, position: V, velocity: V) -> RecordedBoid<V> {
+        RecordedBoid {
+            species,
+            position,
+            velocity,
+        }
+    }
+
+    fn to_csv<V: Vector>(ticks: &[Vec<RecordedBoid<V>>]) -> String {
+        let mut out = format!("{}\n", header::<V>()).into_bytes();
+        for (tick, boids) in ticks.iter().enumerate() {
+            write_tick(&mut out, tick, boids.iter().copied()).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn recordings_read_back_exactly() {
+        let ticks = vec![
+            vec![
+                boid(0, Vec2::new(0.1, -3.0), Vec2::new(1.0 / 3.0, 2.5)),
+                boid(1, Vec2::new(1e-7, 640.0), Vec2::new(-0.0, 99.99)),
+            ],
+            vec![],
+            vec![boid(0, Vec2::new(0.2, -2.9), Vec2::new(1.0 / 3.0, 2.5))],
+        ];
+        let csv = to_csv(&ticks);
+        assert!(csv.starts_with("tick,species,x,y,vx,vy\n0,0,0.1,-3,"));
+
+        let recording = Recording::<Vec2>::parse(&csv).unwrap();
+        assert_eq!(recording.len(), 3);
+        for (tick, boids) in ticks.iter().enumerate() {
+            assert_eq!(recording.tick(tick), boids.as_slice());
+        }
+
+        let deep = vec![vec![boid(2, Vec3::new(1.0, 2.0, 3.0), Vec3::NEG_Z)]];
+        let recording = Recording::<Vec3>::parse(&to_csv(&deep)).unwrap();
+        assert_eq!(recording.tick(0), deep[0].as_slice());
+    }
+
+    #[test]
+    fn broken_recordings_are_rejected() {
+        let flat = to_csv(&[vec![boid(0, Vec2::ONE, Vec2::ONE)]]);
+        assert!(Recording::<Vec3>::parse(&flat).is_err());
+        assert!(Recording::<Vec2>::parse("").is_err());
+
+        for body in [
+            "0,0,1,2,3",
+            "0,0,1,2,3,x",
+            "1,0,1,2,3,4\n0,0,1,2,3,4",
+            // Too far ahead to allocate, or to count one past
+            "1000000000000,0,1,2,3,4",
+            "18446744073709551615,0,1,2,3,4",
+        ] {
+            let csv = format!("{}\n{}", header::<Vec2>(), body);
+            assert!(Recording::<Vec2>::parse(&csv).is_err(), "{}", body);
+        }
+    }
+}
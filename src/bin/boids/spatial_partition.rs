@@ -1,209 +1,901 @@
 // use super::*;
-use bevy::prelude::Vec2;
+use super::vector::Vector;
+use bevy::prelude::{Vec2, Vec3};
+use std::collections::HashMap;
+use std::hash::Hash;
 
+/// Axis-aligned box, a rectangle with `Vec2` and a cuboid with `Vec3`.
 #[derive(Debug, Default, Clone, Copy)]
-pub struct AABB {
-    pub center: Vec2,
-    pub half_dimension: Vec2,
+pub struct AABB<V = Vec2> {
+    pub center: V,
+    pub half_dimension: V,
 }
 
-impl Eq for AABB {}
+impl<V: Vector> Eq for AABB<V> {}
 
-impl PartialEq for AABB {
+impl<V: PartialEq> PartialEq for AABB<V> {
     fn eq(&self, other: &Self) -> bool {
         self.center == other.center && self.half_dimension == other.half_dimension
     }
 }
 
-impl AABB {
-    pub fn new(center: Vec2, half_dimension: Vec2) -> Self {
+impl<V: Vector> AABB<V> {
+    pub fn new(center: V, half_dimension: V) -> Self {
         Self {
             center,
             half_dimension,
         }
     }
 
-    pub fn contains(&self, point: Vec2) -> bool {
-        point.x <= self.center.x + self.half_dimension.x
-            && point.x >= self.center.x - self.half_dimension.x
-            && point.y <= self.center.y + self.half_dimension.y
-            && point.y >= self.center.y - self.half_dimension.y
+    pub fn min(&self) -> V {
+        self.center - self.half_dimension
     }
 
-    pub fn intersects(&self, range: &AABB) -> bool {
-        let one_position = range.center;
-        let two_position = self.center;
+    pub fn max(&self) -> V {
+        self.center + self.half_dimension
+    }
 
-        let one_size = range.half_dimension * 2.0;
-        let two_size = self.half_dimension * 2.0;
+    pub fn contains(&self, point: V) -> bool {
+        let offset = (point - self.center).abs();
+        (0..V::DIM).all(|axis| offset.component(axis) <= self.half_dimension.component(axis))
+    }
 
-        let one_half_size = range.half_dimension;
-        let two_half_size = self.half_dimension;
+    pub fn intersects(&self, range: &AABB<V>) -> bool {
+        // Overlapping on every axis
+        let offset = (range.center - self.center).abs();
+        let reach = range.half_dimension + self.half_dimension;
+        (0..V::DIM).all(|axis| offset.component(axis) <= reach.component(axis))
+    }
 
-        let one_position = Vec2::new(
-            one_position.x - one_half_size.x,
-            one_position.y - one_half_size.y,
-        );
-        let two_position = Vec2::new(
-            two_position.x - two_half_size.x,
-            two_position.y - two_half_size.y,
-        );
+    /// Squared distance from `point` to the closest point of the box, zero when inside.
+    pub fn distance_squared(&self, point: V) -> f32 {
+        let outside = ((point - self.center).abs() - self.half_dimension).max(V::ZERO);
+        outside.length_squared()
+    }
+}
+
+/// Insert/remove/query interface shared by the spatial partitions, so a
+/// simulation can pick whichever suits its distribution at startup.
+pub trait SpatialIndex<T, V: Vector = Vec2> {
+    /// Inserts `data` at `point`, moving it if it is already indexed.
+    fn insert(&mut self, point: V, data: T);
+    /// Moves `id` to `new_pos`. `old_pos` is where the caller last put it, the
+    /// index knows better when the two disagree.
+    fn update(&mut self, id: T, old_pos: V, new_pos: V);
+    /// Removes `id`, returning its last known position.
+    fn remove_by_id(&mut self, id: &T) -> Option<V>;
+    /// Removes every entry stored at exactly `point`.
+    #[allow(dead_code)]
+    fn remove(&mut self, point: V);
+    #[allow(dead_code)]
+    fn query_range(&self, range: &AABB<V>) -> Vec<(V, T)>;
+    /// Returns every point within `radius` of `center`, closest first.
+    fn query_radius(&self, center: V, radius: f32) -> Vec<(V, T)>;
+    /// Returns the `k` points closest to `point`, closest first.
+    fn k_nearest(&self, point: V, k: usize) -> Vec<(V, T)>;
+    #[allow(dead_code)]
+    fn len(&self) -> usize;
+    /// Boundaries of the cells points are sorted into, for drawing the partition.
+    fn cells(&self) -> Vec<AABB<V>>;
+
+    #[allow(dead_code)]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Levels below the root of a tree, zero for flat partitions.
+    fn depth(&self) -> usize {
+        0
+    }
+
+    /// Like [`SpatialIndex::query_range`], but in a toroidal world where `range`
+    /// wraps around the edges of `world_bounds`. Points found across an edge are
+    /// returned shifted next to `range`, so the offset to them is the shortest one
+    /// around the torus. `range` must be smaller than the world.
+    #[allow(dead_code)]
+    fn query_range_wrapped(&self, range: &AABB<V>, world_bounds: &AABB<V>) -> Vec<(V, T)> {
+        wrap_offsets(range, world_bounds)
+            .into_iter()
+            .flat_map(|offset| {
+                self.query_range(&AABB::new(range.center + offset, range.half_dimension))
+                    .into_iter()
+                    .map(move |(p, data)| (p - offset, data))
+            })
+            .collect()
+    }
+
+    /// Like [`SpatialIndex::query_radius`], wrapping around `world_bounds` the same
+    /// way as [`SpatialIndex::query_range_wrapped`].
+    fn query_radius_wrapped(&self, center: V, radius: f32, world_bounds: &AABB<V>) -> Vec<(V, T)> {
+        let range = AABB::new(center, V::splat(radius));
+        let mut found = wrap_offsets(&range, world_bounds)
+            .into_iter()
+            .flat_map(|offset| {
+                self.query_radius(center + offset, radius)
+                    .into_iter()
+                    .map(move |(p, data)| (p - offset, data))
+            })
+            .collect::<Vec<_>>();
+        found.sort_by(|(a, _), (b, _)| {
+            a.distance_squared(center)
+                .total_cmp(&b.distance_squared(center))
+        });
+        found
+    }
 
-        // collision x-axis?
-        let collision_x = one_position.x + one_size.x >= two_position.x
-            && two_position.x + two_size.x >= one_position.x;
-        // collision y-axis?
-        let collision_y = one_position.y + one_size.y >= two_position.y
-            && two_position.y + two_size.y >= one_position.y;
+    /// Like [`SpatialIndex::k_nearest`], wrapping around `world_bounds` the same
+    /// way as [`SpatialIndex::query_range_wrapped`].
+    fn k_nearest_wrapped(&self, point: V, k: usize, world_bounds: &AABB<V>) -> Vec<(V, T)>
+    where
+        T: PartialEq,
+    {
+        // Across an edge the closest copy of `point` is on the far side of the
+        // world, one copy per combination of axes.
+        let size = world_bounds.half_dimension * 2.0;
+        let mut offsets = vec![V::ZERO];
+        for axis in 0..V::DIM {
+            let shift = if point.component(axis) < world_bounds.center.component(axis) {
+                size.component(axis)
+            } else {
+                -size.component(axis)
+            };
+            let shifted = offsets
+                .iter()
+                .map(|offset| *offset + V::axis(axis) * shift)
+                .collect::<Vec<_>>();
+            offsets.extend(shifted);
+        }
 
-        collision_x && collision_y
+        // The k closest around the torus are among the k closest to a copy.
+        let mut found: Vec<(V, T)> = Vec::new();
+        for offset in offsets {
+            for (p, data) in self.k_nearest(point + offset, k) {
+                let p = p - offset;
+                match found.iter_mut().find(|(_, other)| *other == data) {
+                    Some(entry) if p.distance_squared(point) < entry.0.distance_squared(point) => {
+                        entry.0 = p
+                    }
+                    Some(_) => {}
+                    None => found.push((p, data)),
+                }
+            }
+        }
+        found.sort_by(|(a, _), (b, _)| {
+            a.distance_squared(point)
+                .total_cmp(&b.distance_squared(point))
+        });
+        found.truncate(k);
+        found
     }
 }
 
+/// Offsets at which `range` has to be queried to cover the parts of it that
+/// wrap around the edges of `world_bounds`, always starting with no offset.
+fn wrap_offsets<V: Vector>(range: &AABB<V>, world_bounds: &AABB<V>) -> Vec<V> {
+    debug_assert!(
+        (0..V::DIM).all(|axis| range.half_dimension.component(axis)
+            < world_bounds.half_dimension.component(axis)),
+        "wrapped queries must be smaller than the world"
+    );
+
+    let size = world_bounds.half_dimension * 2.0;
+    let mut offsets = vec![V::ZERO];
+
+    for axis in 0..V::DIM {
+        let shift = if range.min().component(axis) <= world_bounds.min().component(axis) {
+            size.component(axis)
+        } else if range.max().component(axis) >= world_bounds.max().component(axis) {
+            -size.component(axis)
+        } else {
+            continue;
+        };
+
+        let shifted = offsets
+            .iter()
+            .map(|offset| *offset + V::axis(axis) * shift)
+            .collect::<Vec<_>>();
+        offsets.extend(shifted);
+    }
+
+    offsets
+}
+
+/// Number of points a leaf holds before it gets subdivided.
+pub const DEFAULT_CAPACITY: usize = 4;
+/// Depth past which leaves stop subdividing and simply grow.
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+
 #[derive(Debug, PartialEq)]
-enum QuadTreeData {
-    Leaf(Vec<(Vec2, usize)>),
-    Node(Vec<QuadTree>),
+enum TreeData<T, V> {
+    Leaf(Vec<(V, T)>),
+    Node(Vec<TreeNode<T, V>>),
     Empty,
 }
 
 #[derive(Debug, PartialEq)]
-pub struct QuadTree {
-    root: QuadTreeData,
-    pub boundary: AABB,
+struct TreeNode<T, V> {
+    data: TreeData<T, V>,
+    boundary: AABB<V>,
+    depth: usize,
+}
+
+/// Point tree storing an arbitrary payload alongside each position, each node
+/// splits into `2^V::DIM` children: a quadtree with `Vec2`, an octree with `Vec3`.
+///
+/// Payloads double as ids: inserting the same payload twice moves it, and the
+/// tree remembers where each one lives so it can be moved or removed without a
+/// rebuild.
+#[derive(Debug)]
+pub struct Tree<T, V = Vec2> {
+    root: TreeNode<T, V>,
+    capacity: usize,
+    max_depth: usize,
+    locations: HashMap<T, V>,
+}
+
+/// A [`Tree`] in the plane, splitting each node into quadrants.
+#[allow(dead_code)]
+pub type QuadTree<T> = Tree<T, Vec2>;
+/// A [`Tree`] in space, splitting each node into octants.
+#[allow(dead_code)]
+pub type Octree<T> = Tree<T, Vec3>;
+
+impl<T, V: Vector> Tree<T, V> {
+    pub fn new(boundary: AABB<V>) -> Self {
+        Self::with_config(boundary, DEFAULT_CAPACITY, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Creates a tree whose leaves hold up to `capacity` points, and which
+    /// never subdivides deeper than `max_depth` (the root is depth 0).
+    pub fn with_config(boundary: AABB<V>, capacity: usize, max_depth: usize) -> Self {
+        assert!(capacity > 0, "a tree leaf must hold at least one point");
+        Self {
+            root: TreeNode::new(boundary, 0),
+            capacity,
+            max_depth,
+            locations: HashMap::new(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn boundary(&self) -> AABB<V> {
+        self.root.boundary
+    }
+
+    /// Boundaries of the leaves, empty ones included.
+    pub fn cells(&self) -> Vec<AABB<V>> {
+        let mut cells = Vec::new();
+        self.root.leaves(&mut cells);
+        cells
+    }
+
+    /// Depth of the deepest leaf, the root alone is depth 0.
+    pub fn depth(&self) -> usize {
+        self.root.max_depth()
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+}
+
+impl<T: Clone + Eq + Hash, V: Vector> Tree<T, V> {
+    /// Inserts `data` at `point`, moving it if it is already in the tree.
+    /// Points outside the tree boundary are dropped.
+    pub fn insert(&mut self, point: V, data: T) {
+        if let Some(old_point) = self.locations.remove(&data) {
+            self.root.remove_id(&data, old_point, self.capacity);
+        }
+
+        if self.root.boundary.contains(point) {
+            self.locations.insert(data.clone(), point);
+            self.root.insert(point, data, self.capacity, self.max_depth);
+        }
+    }
+
+    /// Moves `id` to `new_pos`, collapsing the subtree it leaves if it becomes
+    /// underfull. `old_pos` is only a hint, the tree looks up where `id` really is
+    /// so a stale one can't leave it behind twice.
+    pub fn update(&mut self, id: T, _old_pos: V, new_pos: V) {
+        if self.locations.get(&id) == Some(&new_pos) {
+            return;
+        }
+
+        self.insert(new_pos, id);
+    }
+
+    /// Removes `id` from the tree, returning its last known position.
+    pub fn remove_by_id(&mut self, id: &T) -> Option<V> {
+        let point = self.locations.remove(id)?;
+        self.root.remove_id(id, point, self.capacity);
+        Some(point)
+    }
+
+    /// Removes every entry stored at exactly `point`.
+    #[allow(dead_code)]
+    pub fn remove(&mut self, point: V) {
+        if !self.root.boundary.contains(point) {
+            return;
+        }
+
+        let mut removed = Vec::new();
+        self.root.remove(point, self.capacity, &mut removed);
+        for id in removed {
+            self.locations.remove(&id);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn position(&self, id: &T) -> Option<V> {
+        self.locations.get(id).copied()
+    }
+}
+
+impl<T: Clone, V: Vector> Tree<T, V> {
+    #[allow(dead_code)]
+    pub fn query_range(&self, range: &AABB<V>) -> Vec<(V, T)> {
+        let mut found = Vec::new();
+        self.root.query_range(range, &mut found);
+        found
+    }
+
+    /// Returns every point within `radius` of `center`, closest first.
+    pub fn query_radius(&self, center: V, radius: f32) -> Vec<(V, T)> {
+        let mut found = Vec::new();
+        self.root.query_radius(center, radius * radius, &mut found);
+        found.sort_by(|(a, _), (b, _)| {
+            a.distance_squared(center)
+                .total_cmp(&b.distance_squared(center))
+        });
+        found
+    }
+
+    /// Returns the `k` points closest to `point`, closest first.
+    pub fn k_nearest(&self, point: V, k: usize) -> Vec<(V, T)> {
+        let mut best = Vec::with_capacity(k + 1);
+        if k > 0 {
+            self.root.k_nearest(point, k, &mut best);
+        }
+        best.into_iter().map(|(_, p, data)| (p, data)).collect()
+    }
 }
 
-impl QuadTree {
-    pub fn new(boundary: AABB) -> Self {
+impl<T, V: Vector> TreeNode<T, V> {
+    fn new(boundary: AABB<V>, depth: usize) -> Self {
         Self {
-            root: QuadTreeData::Empty,
+            data: TreeData::Empty,
             boundary,
+            depth,
         }
     }
 
-    pub fn insert(&mut self, point: Vec2, data: usize) {
-        if self.boundary.contains(point) {
-            match &mut self.root {
-                QuadTreeData::Empty => {
-                    self.root = QuadTreeData::Leaf(vec![(point, data)]);
+    fn leaves(&self, cells: &mut Vec<AABB<V>>) {
+        match &self.data {
+            TreeData::Node(nodes) => nodes.iter().for_each(|node| node.leaves(cells)),
+            _ => cells.push(self.boundary),
+        }
+    }
+
+    fn max_depth(&self) -> usize {
+        match &self.data {
+            TreeData::Node(nodes) => nodes.iter().map(TreeNode::max_depth).max().unwrap_or(0),
+            _ => self.depth,
+        }
+    }
+
+    /// Index of the child owning `point`, bit `axis` is set when the point is
+    /// on the positive side of that axis. Children own the half-open range
+    /// `[min, max)` on each axis, so a point on a subdivision line belongs to
+    /// exactly one of them. Points on the far edges of the root fall into the
+    /// children along those edges.
+    fn child(&self, point: V) -> usize {
+        let center = self.boundary.center;
+        (0..V::DIM)
+            .filter(|&axis| point.component(axis) >= center.component(axis))
+            .fold(0, |index, axis| index | 1 << axis)
+    }
+
+    /// Only called with points inside the tree boundary.
+    fn insert(&mut self, point: V, data: T, capacity: usize, max_depth: usize) {
+        match &mut self.data {
+            TreeData::Empty => {
+                self.data = TreeData::Leaf(vec![(point, data)]);
+            }
+            TreeData::Node(_) => {
+                let child = self.child(point);
+                if let TreeData::Node(nodes) = &mut self.data {
+                    nodes[child].insert(point, data, capacity, max_depth);
                 }
-                QuadTreeData::Node(ref mut nodes) => {
-                    nodes[0].insert(point, data);
-                    nodes[1].insert(point, data);
-                    nodes[2].insert(point, data);
-                    nodes[3].insert(point, data);
+            }
+            TreeData::Leaf(ref mut points) => {
+                if points.len() < capacity || self.depth >= max_depth {
+                    points.push((point, data));
+                } else {
+                    self.subdivide(capacity, max_depth);
+                    self.insert(point, data, capacity, max_depth);
                 }
-                QuadTreeData::Leaf(ref mut points) => {
-                    if points.len() < 4 {
-                        points.push((point, data));
+            }
+        }
+    }
+
+    fn subdivide(&mut self, capacity: usize, max_depth: usize) {
+        let half_size = self.boundary.half_dimension / 2.0;
+        let depth = self.depth + 1;
+
+        if let TreeData::Leaf(data) = std::mem::replace(&mut self.data, TreeData::Empty) {
+            let mut nodes = (0..1 << V::DIM)
+                .map(|child| {
+                    let offset = (0..V::DIM)
+                        .map(|axis| {
+                            let side = if child & 1 << axis != 0 { 1.0 } else { -1.0 };
+                            V::axis(axis) * half_size.component(axis) * side
+                        })
+                        .sum();
+                    TreeNode::new(AABB::new(self.boundary.center + offset, half_size), depth)
+                })
+                .collect::<Vec<_>>();
+
+            for (point, value) in data {
+                let child = self.child(point);
+                nodes[child].insert(point, value, capacity, max_depth);
+            }
+
+            self.data = TreeData::Node(nodes)
+        }
+    }
+
+    fn remove(&mut self, point: V, capacity: usize, removed: &mut Vec<T>) {
+        let child = self.child(point);
+        match &mut self.data {
+            TreeData::Leaf(ref mut data) => {
+                let mut i = 0;
+                while i < data.len() {
+                    if data[i].0 == point {
+                        removed.push(data.remove(i).1);
                     } else {
-                        self.subdivide();
-                        if let QuadTreeData::Node(nodes) = &mut self.root {
-                            nodes[0].insert(point, data);
-                            nodes[1].insert(point, data);
-                            nodes[2].insert(point, data);
-                            nodes[3].insert(point, data);
-                        }
+                        i += 1;
                     }
                 }
+                if data.is_empty() {
+                    self.data = TreeData::Empty
+                }
             }
+            TreeData::Node(ref mut nodes) => {
+                nodes[child].remove(point, capacity, removed);
+                self.collapse(capacity);
+            }
+            TreeData::Empty => {}
         }
     }
 
-    pub fn subdivide(&mut self) {
-        let pos = self.boundary.center;
-        let half_size = self.boundary.half_dimension;
+    /// Removes the entry `id`, looking for it only in the leaf owning `point`.
+    fn remove_id(&mut self, id: &T, point: V, capacity: usize)
+    where
+        T: PartialEq,
+    {
+        let child = self.child(point);
+        match &mut self.data {
+            TreeData::Leaf(ref mut data) => {
+                data.retain(|(_, value)| value != id);
+                if data.is_empty() {
+                    self.data = TreeData::Empty
+                }
+            }
+            TreeData::Node(ref mut nodes) => {
+                nodes[child].remove_id(id, point, capacity);
+                self.collapse(capacity);
+            }
+            TreeData::Empty => {}
+        }
+    }
 
-        let ne = AABB::new(pos + half_size / 2.0, half_size / 2.0);
-        let nw = AABB::new(pos - half_size / 2.0, half_size / 2.0);
-        let neg_half_size = half_size * Vec2::new(1.0, -1.0);
-        let se = AABB::new(pos + neg_half_size / 2.0, half_size / 2.0);
-        let sw = AABB::new(pos - neg_half_size / 2.0, half_size / 2.0);
+    /// Merges the children back into a single leaf once they hold at most half a
+    /// leaf worth of entries. Waiting for half rather than a full leaf avoids
+    /// splitting and merging over and over when an entry hovers around a border.
+    fn collapse(&mut self, capacity: usize) {
+        if let TreeData::Node(nodes) = &mut self.data {
+            let mut count = 0;
+            for node in nodes.iter() {
+                match &node.data {
+                    TreeData::Leaf(data) => count += data.len(),
+                    TreeData::Node(_) => return,
+                    TreeData::Empty => {}
+                }
+            }
 
-        if let QuadTreeData::Leaf(data) = &self.root {
-            let mut trees = vec![
-                QuadTree::new(ne),
-                QuadTree::new(nw),
-                QuadTree::new(se),
-                QuadTree::new(sw),
-            ];
+            if count > capacity / 2 {
+                return;
+            }
 
-            for tree in &mut trees {
-                for point in data {
-                    tree.insert(point.0, point.1);
+            let mut merged: Vec<(V, T)> = Vec::with_capacity(count);
+            for node in nodes.iter_mut() {
+                if let TreeData::Leaf(data) = std::mem::replace(&mut node.data, TreeData::Empty) {
+                    merged.extend(data);
                 }
             }
 
-            self.root = QuadTreeData::Node(trees)
+            self.data = if merged.is_empty() {
+                TreeData::Empty
+            } else {
+                TreeData::Leaf(merged)
+            };
         }
     }
+}
 
-    pub fn remove(&mut self, point: Vec2) {
-        if self.boundary.contains(point) {
-            match &mut self.root {
-                QuadTreeData::Leaf(ref mut data) => {
-                    let mut to_remove = Vec::new();
-                    for i in 0..data.len() {
-                        if data[i].0 == point {
-                            to_remove.push(i);
-                        }
+impl<T: Clone, V: Vector> TreeNode<T, V> {
+    fn query_range(&self, range: &AABB<V>, found: &mut Vec<(V, T)>) {
+        if self.boundary.intersects(range) {
+            match &self.data {
+                TreeData::Leaf(data) => {
+                    found.extend(data.iter().filter(|(p, _)| range.contains(*p)).cloned());
+                }
+                TreeData::Node(nodes) => {
+                    for node in nodes {
+                        node.query_range(range, found);
                     }
-                    for i in to_remove {
-                        data.remove(i);
+                }
+                TreeData::Empty => {}
+            }
+        }
+    }
+
+    fn query_radius(&self, center: V, radius_squared: f32, found: &mut Vec<(V, T)>) {
+        if self.boundary.distance_squared(center) <= radius_squared {
+            match &self.data {
+                TreeData::Leaf(data) => {
+                    found.extend(
+                        data.iter()
+                            .filter(|(p, _)| p.distance_squared(center) <= radius_squared)
+                            .cloned(),
+                    );
+                }
+                TreeData::Node(nodes) => {
+                    for node in nodes {
+                        node.query_radius(center, radius_squared, found);
                     }
-                    if data.is_empty() {
-                        self.root = QuadTreeData::Empty
+                }
+                TreeData::Empty => {}
+            }
+        }
+    }
+
+    /// `best` is kept sorted by squared distance and never grows past `k`.
+    fn k_nearest(&self, point: V, k: usize, best: &mut Vec<(f32, V, T)>) {
+        if best.len() == k && self.boundary.distance_squared(point) > best[k - 1].0 {
+            return;
+        }
+
+        match &self.data {
+            TreeData::Leaf(data) => {
+                for (p, value) in data {
+                    let dist = p.distance_squared(point);
+                    if best.len() < k || dist < best[k - 1].0 {
+                        let index = best.partition_point(|(d, _, _)| *d <= dist);
+                        best.insert(index, (dist, *p, value.clone()));
+                        best.truncate(k);
                     }
                 }
-                QuadTreeData::Node(ref mut nodes) => {
-                    nodes[0].remove(point);
-                    nodes[1].remove(point);
-                    nodes[2].remove(point);
-                    nodes[3].remove(point);
+            }
+            TreeData::Node(nodes) => {
+                // Visit the closest children first so the others are more likely to be pruned.
+                let mut order = nodes.iter().collect::<Vec<_>>();
+                order.sort_by(|a, b| {
+                    a.boundary
+                        .distance_squared(point)
+                        .total_cmp(&b.boundary.distance_squared(point))
+                });
+                for node in order {
+                    node.k_nearest(point, k, best);
                 }
-                QuadTreeData::Empty => {}
             }
+            TreeData::Empty => {}
         }
     }
+}
 
-    pub fn query_range(&self, range: &AABB) -> Vec<(Vec2, usize)> {
-        let mut found = Vec::new();
+impl<T: Clone + Eq + Hash, V: Vector> SpatialIndex<T, V> for Tree<T, V> {
+    fn insert(&mut self, point: V, data: T) {
+        Tree::insert(self, point, data)
+    }
 
-        if self.boundary.intersects(range) {
-            match &self.root {
-                QuadTreeData::Leaf(data) => {
-                    found.extend(data.iter().filter(|(p, _)| range.contains(*p)));
-                }
-                QuadTreeData::Node(nodes) => {
-                    found.extend(nodes[0].query_range(range));
-                    found.extend(nodes[1].query_range(range));
-                    found.extend(nodes[2].query_range(range));
-                    found.extend(nodes[3].query_range(range));
+    fn update(&mut self, id: T, old_pos: V, new_pos: V) {
+        Tree::update(self, id, old_pos, new_pos)
+    }
+
+    fn remove_by_id(&mut self, id: &T) -> Option<V> {
+        Tree::remove_by_id(self, id)
+    }
+
+    fn remove(&mut self, point: V) {
+        Tree::remove(self, point)
+    }
+
+    fn query_range(&self, range: &AABB<V>) -> Vec<(V, T)> {
+        Tree::query_range(self, range)
+    }
+
+    fn query_radius(&self, center: V, radius: f32) -> Vec<(V, T)> {
+        Tree::query_radius(self, center, radius)
+    }
+
+    fn k_nearest(&self, point: V, k: usize) -> Vec<(V, T)> {
+        Tree::k_nearest(self, point, k)
+    }
+
+    fn len(&self) -> usize {
+        Tree::len(self)
+    }
+
+    fn cells(&self) -> Vec<AABB<V>> {
+        Tree::cells(self)
+    }
+
+    fn depth(&self) -> usize {
+        Tree::depth(self)
+    }
+}
+
+/// Cell of a [`SpatialHashGrid`], the axes a vector doesn't have stay at zero.
+type CellCoordinate = [i32; 3];
+
+/// Cells of the block between `min` and `max`, both included.
+fn block(min: [i64; 3], max: [i64; 3]) -> impl Iterator<Item = CellCoordinate> {
+    (min[0]..=max[0]).flat_map(move |x| {
+        (min[1]..=max[1])
+            .flat_map(move |y| (min[2]..=max[2]).map(move |z| [x as i32, y as i32, z as i32]))
+    })
+}
+
+/// Uniform grid hashing points into fixed-size square (or cubic) cells.
+///
+/// Unlike the trees it is unbounded, and when every query uses roughly the
+/// same radius a cell size close to that radius keeps each query to a 3x3 block
+/// of cells (3x3x3 in 3D).
+#[derive(Debug)]
+pub struct SpatialHashGrid<T, V = Vec2> {
+    cell_size: f32,
+    cells: HashMap<CellCoordinate, Vec<(V, T)>>,
+    locations: HashMap<T, V>,
+}
+
+impl<T, V: Vector> SpatialHashGrid<T, V> {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "grid cells must have a positive size");
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            locations: HashMap::new(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    fn cell(&self, point: V) -> CellCoordinate {
+        let mut cell = [0; 3];
+        for (axis, coordinate) in cell.iter_mut().enumerate().take(V::DIM) {
+            *coordinate = (point.component(axis) / self.cell_size).floor() as i32;
+        }
+        cell
+    }
+
+    /// Bounding box of the occupied cells, used to stop searches that would
+    /// otherwise walk forever over empty space.
+    fn occupied_bounds(&self) -> Option<(CellCoordinate, CellCoordinate)> {
+        self.cells.keys().fold(None, |bounds, &cell| match bounds {
+            None => Some((cell, cell)),
+            Some((min, max)) => Some((
+                [0, 1, 2].map(|axis| min[axis].min(cell[axis])),
+                [0, 1, 2].map(|axis| max[axis].max(cell[axis])),
+            )),
+        })
+    }
+}
+
+impl<T: Clone + Eq + Hash, V: Vector> SpatialHashGrid<T, V> {
+    fn remove_from_cell(&mut self, id: &T, point: V) {
+        let cell = self.cell(point);
+        if let Some(entries) = self.cells.get_mut(&cell) {
+            entries.retain(|(_, value)| value != id);
+            if entries.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    fn cells_in(&self, min: V, max: V) -> impl Iterator<Item = &Vec<(V, T)>> {
+        block(self.cell(min).map(i64::from), self.cell(max).map(i64::from))
+            .filter_map(|cell| self.cells.get(&cell))
+    }
+}
+
+impl<T: Clone + Eq + Hash, V: Vector> SpatialIndex<T, V> for SpatialHashGrid<T, V> {
+    fn insert(&mut self, point: V, data: T) {
+        if let Some(old_point) = self.locations.remove(&data) {
+            self.remove_from_cell(&data, old_point);
+        }
+
+        let cell = self.cell(point);
+        self.cells
+            .entry(cell)
+            .or_default()
+            .push((point, data.clone()));
+        self.locations.insert(data, point);
+    }
+
+    fn update(&mut self, id: T, _old_pos: V, new_pos: V) {
+        // Where the grid has it, a stale `old_pos` would leave it behind twice
+        if let Some(&old_pos) = self.locations.get(&id) {
+            if self.cell(old_pos) == self.cell(new_pos) {
+                if let Some(entry) = self
+                    .cells
+                    .get_mut(&self.cell(old_pos))
+                    .and_then(|entries| entries.iter_mut().find(|(_, value)| *value == id))
+                {
+                    entry.0 = new_pos;
+                    self.locations.insert(id, new_pos);
+                    return;
                 }
-                QuadTreeData::Empty => {}
             }
         }
 
+        self.insert(new_pos, id);
+    }
+
+    fn remove_by_id(&mut self, id: &T) -> Option<V> {
+        let point = self.locations.remove(id)?;
+        self.remove_from_cell(id, point);
+        Some(point)
+    }
+
+    fn remove(&mut self, point: V) {
+        let cell = self.cell(point);
+        if let Some(entries) = self.cells.get_mut(&cell) {
+            for (_, id) in entries.iter().filter(|(p, _)| *p == point) {
+                self.locations.remove(id);
+            }
+            entries.retain(|(p, _)| *p != point);
+            if entries.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    fn query_range(&self, range: &AABB<V>) -> Vec<(V, T)> {
+        self.cells_in(range.min(), range.max())
+            .flatten()
+            .filter(|(p, _)| range.contains(*p))
+            .cloned()
+            .collect()
+    }
+
+    fn query_radius(&self, center: V, radius: f32) -> Vec<(V, T)> {
+        let radius_squared = radius * radius;
+        let mut found = self
+            .cells_in(center - V::splat(radius), center + V::splat(radius))
+            .flatten()
+            .filter(|(p, _)| p.distance_squared(center) <= radius_squared)
+            .cloned()
+            .collect::<Vec<_>>();
+        found.sort_by(|(a, _), (b, _)| {
+            a.distance_squared(center)
+                .total_cmp(&b.distance_squared(center))
+        });
         found
     }
+
+    fn k_nearest(&self, point: V, k: usize) -> Vec<(V, T)> {
+        let (low, high) = match self.occupied_bounds() {
+            Some(bounds) if k > 0 => bounds,
+            _ => return Vec::new(),
+        };
+
+        // In i64, cell() saturates for far away points and the rings around them
+        // would overflow i32.
+        let center = self.cell(point).map(i64::from);
+        let (low, high) = (low.map(i64::from), high.map(i64::from));
+        // First ring reaching an occupied cell, and the furthest one that still can.
+        let first_ring = (0..3)
+            .map(|axis| (low[axis] - center[axis]).max(center[axis] - high[axis]))
+            .fold(0, i64::max);
+        let max_ring = (0..3)
+            .map(|axis| (center[axis] - low[axis]).max(high[axis] - center[axis]))
+            .fold(0, i64::max);
+
+        let mut best: Vec<(f32, V, T)> = Vec::with_capacity(k + 1);
+        let visit = |best: &mut Vec<(f32, V, T)>, cell: CellCoordinate| {
+            if let Some(entries) = self.cells.get(&cell) {
+                for (p, value) in entries {
+                    let dist = p.distance_squared(point);
+                    if best.len() < k || dist < best[k - 1].0 {
+                        let index = best.partition_point(|(d, _, _)| *d <= dist);
+                        best.insert(index, (dist, *p, value.clone()));
+                        best.truncate(k);
+                    }
+                }
+            }
+        };
+
+        if first_ring == 0 {
+            visit(&mut best, center.map(|coordinate| coordinate as i32));
+        }
+        for ring in first_ring.max(1)..=max_ring {
+            // Anything in this ring is at least `ring - 1` cells away from `point`.
+            let ring_distance = (ring - 1) as f32 * self.cell_size;
+            if best.len() == k && ring_distance * ring_distance > best[k - 1].0 {
+                break;
+            }
+
+            // Only the faces of the ring, each cell once: a face leaves out the
+            // edges it shares with the faces of the previous axes. Clipped to the
+            // occupied cells, so within i32.
+            for axis in 0..V::DIM {
+                for side in [center[axis] - ring, center[axis] + ring] {
+                    if side < low[axis] || side > high[axis] {
+                        continue;
+                    }
+                    let inset = |other: usize| i64::from(other < axis);
+                    let mut min = [0, 1, 2]
+                        .map(|other| (center[other] - ring + inset(other)).max(low[other]));
+                    let mut max = [0, 1, 2]
+                        .map(|other| (center[other] + ring - inset(other)).min(high[other]));
+                    min[axis] = side;
+                    max[axis] = side;
+                    for cell in block(min, max) {
+                        visit(&mut best, cell);
+                    }
+                }
+            }
+        }
+
+        best.into_iter().map(|(_, p, data)| (p, data)).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// The occupied cells, the grid is unbounded.
+    fn cells(&self) -> Vec<AABB<V>> {
+        let half_size = V::splat(self.cell_size / 2.0);
+        self.cells
+            .keys()
+            .map(|cell| {
+                let corner = (0..V::DIM)
+                    .map(|axis| V::axis(axis) * cell[axis] as f32)
+                    .sum::<V>();
+                AABB::new(corner * self.cell_size + half_size, half_size)
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
     #[test]
     fn create_quadtree() {
-        let tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(0.5, 0.5)));
-        assert_eq!(tree.root, QuadTreeData::Empty);
+        let tree: Tree<usize> = Tree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(0.5, 0.5)));
+        assert_eq!(tree.root.data, TreeData::Empty);
     }
     #[test]
     fn add_element() {
-        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
+        let mut tree = Tree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
         // println!("{:?}", tree);
         tree.insert(Vec2::new(42.0, 0.0), 0);
         // println!("{:?}", tree);
-        if let QuadTreeData::Leaf(data) = tree.root {
+        if let TreeData::Leaf(data) = tree.root.data {
             assert_eq!(data.len(), 1);
         } else {
             unreachable!()
@@ -217,7 +909,7 @@ mod tests {
         tree.insert(Vec2::new(43.0, 0.0), 1);
         tree.insert(Vec2::new(44.0, 0.0), 2);
         tree.insert(Vec2::new(45.0, 0.0), 3);
-        if let QuadTreeData::Leaf(data) = tree.root {
+        if let TreeData::Leaf(data) = tree.root.data {
             assert_eq!(data.len(), 4);
         } else {
             unreachable!()
@@ -231,7 +923,7 @@ mod tests {
         tree.insert(Vec2::new(20.0, 10.0), 1);
         tree.insert(Vec2::new(-20.0, -10.0), 2);
         tree.insert(Vec2::new(20.0, -10.0), 3);
-        if let QuadTreeData::Leaf(data) = &tree.root {
+        if let TreeData::Leaf(data) = &tree.root.data {
             assert_eq!(data.len(), 4);
         } else {
             unreachable!()
@@ -239,23 +931,23 @@ mod tests {
 
         tree.insert(Vec2::new(22.0, 10.0), 4);
         println!("{:#?}", tree);
-        if let QuadTreeData::Node(data) = &tree.root {
-            if let QuadTreeData::Leaf(data) = &data[0].root {
+        if let TreeData::Node(data) = &tree.root.data {
+            if let TreeData::Leaf(data) = &data[3].data {
                 assert_eq!(data.len(), 2);
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Leaf(data) = &data[1].root {
+            if let TreeData::Leaf(data) = &data[0].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Leaf(data) = &data[2].root {
+            if let TreeData::Leaf(data) = &data[1].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Leaf(data) = &data[3].root {
+            if let TreeData::Leaf(data) = &data[2].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
@@ -272,7 +964,7 @@ mod tests {
         tree.insert(Vec2::new(20.0, 10.0), 1);
         tree.insert(Vec2::new(-20.0, -10.0), 2);
         tree.insert(Vec2::new(20.0, -10.0), 3);
-        if let QuadTreeData::Leaf(data) = &tree.root {
+        if let TreeData::Leaf(data) = &tree.root.data {
             assert_eq!(data.len(), 4);
         } else {
             unreachable!()
@@ -283,23 +975,23 @@ mod tests {
         tree.remove(Vec2::new(20.0, 10.0));
 
         // println!("{:#?}", tree);
-        if let QuadTreeData::Node(data) = &tree.root {
-            if let QuadTreeData::Leaf(data) = &data[0].root {
+        if let TreeData::Node(data) = &tree.root.data {
+            if let TreeData::Leaf(data) = &data[3].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Leaf(data) = &data[1].root {
+            if let TreeData::Leaf(data) = &data[0].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Leaf(data) = &data[2].root {
+            if let TreeData::Leaf(data) = &data[1].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Leaf(data) = &data[3].root {
+            if let TreeData::Leaf(data) = &data[2].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
@@ -310,22 +1002,22 @@ mod tests {
 
         tree.remove(Vec2::new(-20.0, -10.0));
 
-        if let QuadTreeData::Node(data) = &tree.root {
-            if let QuadTreeData::Leaf(data) = &data[0].root {
+        if let TreeData::Node(data) = &tree.root.data {
+            if let TreeData::Leaf(data) = &data[3].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Empty = &data[1].root {
+            if let TreeData::Empty = &data[0].data {
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Leaf(data) = &data[2].root {
+            if let TreeData::Leaf(data) = &data[1].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
             }
-            if let QuadTreeData::Leaf(data) = &data[3].root {
+            if let TreeData::Leaf(data) = &data[2].data {
                 assert_eq!(data.len(), 1);
             } else {
                 unreachable!();
@@ -353,4 +1045,806 @@ mod tests {
         let res = tree.query_range(&AABB::new(Vec2::new(0.0, 0.0), Vec2::new(100.0, 100.0)));
         assert_eq!(res.len(), 5);
     }
+
+    #[test]
+    fn generic_payload() {
+        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
+        tree.insert(Vec2::new(-20.0, 10.0), "first");
+        tree.insert(Vec2::new(20.0, -10.0), "second");
+
+        let res = tree.query_range(&AABB::new(Vec2::new(20.0, -10.0), Vec2::new(1.0, 1.0)));
+        assert_eq!(res, vec![(Vec2::new(20.0, -10.0), "second")]);
+    }
+
+    #[test]
+    fn custom_capacity() {
+        let mut tree = QuadTree::with_config(
+            AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)),
+            2,
+            DEFAULT_MAX_DEPTH,
+        );
+        tree.insert(Vec2::new(-20.0, 10.0), 0);
+        tree.insert(Vec2::new(20.0, 10.0), 1);
+        assert!(matches!(tree.root.data, TreeData::Leaf(_)));
+
+        tree.insert(Vec2::new(20.0, -10.0), 2);
+        assert!(matches!(tree.root.data, TreeData::Node(_)));
+        assert_eq!(tree.query_range(&tree.boundary()).len(), 3);
+    }
+
+    #[test]
+    fn max_depth_stops_subdivision() {
+        let mut tree =
+            QuadTree::with_config(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)), 1, 0);
+        for i in 0..10 {
+            tree.insert(Vec2::new(i as f32, i as f32), i);
+        }
+
+        if let TreeData::Leaf(data) = &tree.root.data {
+            assert_eq!(data.len(), 10);
+        } else {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn subdivided_nodes_track_depth() {
+        let mut tree =
+            QuadTree::with_config(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)), 1, 2);
+        tree.insert(Vec2::new(10.0, 10.0), 0);
+        tree.insert(Vec2::new(12.0, 12.0), 1);
+        tree.insert(Vec2::new(14.0, 14.0), 2);
+
+        if let TreeData::Node(nodes) = &tree.root.data {
+            assert!(nodes.iter().all(|node| node.depth == 1));
+            if let TreeData::Node(nodes) = &nodes[3].data {
+                assert!(nodes.iter().all(|node| node.depth == 2));
+                assert!(nodes
+                    .iter()
+                    .all(|node| !matches!(node.data, TreeData::Node(_))));
+            } else {
+                unreachable!()
+            }
+        } else {
+            unreachable!()
+        }
+        assert_eq!(tree.query_range(&tree.boundary()).len(), 3);
+    }
+
+    #[test]
+    fn ignores_points_outside_boundary() {
+        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
+        tree.insert(Vec2::new(100.0, 0.0), 0);
+        assert_eq!(tree.root.data, TreeData::Empty);
+    }
+
+    #[test]
+    fn aabb_distance() {
+        let aabb = AABB::new(Vec2::new(0.0, 0.0), Vec2::new(10.0, 10.0));
+        assert_eq!(aabb.distance_squared(Vec2::new(5.0, -5.0)), 0.0);
+        assert_eq!(aabb.distance_squared(Vec2::new(13.0, 0.0)), 9.0);
+        assert_eq!(aabb.distance_squared(Vec2::new(13.0, -14.0)), 25.0);
+    }
+
+    fn scattered_tree() -> QuadTree<usize> {
+        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
+        let points = [
+            Vec2::new(-20.0, 10.0),
+            Vec2::new(20.0, 10.0),
+            Vec2::new(-20.0, -10.0),
+            Vec2::new(20.0, -10.0),
+            Vec2::new(22.0, 10.0),
+            Vec2::new(5.0, 3.0),
+            Vec2::new(-41.0, 37.0),
+            Vec2::new(33.0, -29.0),
+        ];
+        for (i, point) in points.iter().enumerate() {
+            tree.insert(*point, i);
+        }
+        tree
+    }
+
+    #[test]
+    fn query_radius_elements() {
+        let tree = scattered_tree();
+
+        let res = tree.query_radius(Vec2::new(20.0, 10.0), 3.0);
+        assert_eq!(
+            res,
+            vec![(Vec2::new(20.0, 10.0), 1), (Vec2::new(22.0, 10.0), 4)]
+        );
+
+        // (20, -10) is inside the bounding box of the circle but not inside the circle
+        let res = tree.query_radius(Vec2::new(5.0, 3.0), 19.0);
+        let ids = res.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![5, 1, 4]);
+
+        assert!(tree.query_radius(Vec2::new(0.0, -40.0), 5.0).is_empty());
+    }
+
+    #[test]
+    fn query_radius_matches_brute_force() {
+        let tree = scattered_tree();
+        let all = tree.query_range(&tree.boundary());
+
+        for radius in [0.0, 5.0, 25.0, 60.0, 200.0] {
+            let center = Vec2::new(3.0, -7.0);
+            let mut expected = all
+                .iter()
+                .filter(|(p, _)| p.distance(center) <= radius)
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            let mut found = tree
+                .query_radius(center, radius)
+                .iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            expected.sort_unstable();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn k_nearest_elements() {
+        let tree = scattered_tree();
+
+        let res = tree.k_nearest(Vec2::new(21.0, 9.0), 3);
+        let ids = res.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 4, 5]);
+
+        let res = tree.k_nearest(Vec2::new(-45.0, 45.0), 1);
+        assert_eq!(res, vec![(Vec2::new(-41.0, 37.0), 6)]);
+
+        assert!(tree.k_nearest(Vec2::ZERO, 0).is_empty());
+        assert_eq!(tree.k_nearest(Vec2::ZERO, 100).len(), 8);
+    }
+
+    #[test]
+    fn k_nearest_is_sorted() {
+        let tree = scattered_tree();
+        let point = Vec2::new(-3.0, 4.0);
+        let res = tree.k_nearest(point, 8);
+        for pair in res.windows(2) {
+            assert!(pair[0].0.distance(point) <= pair[1].0.distance(point));
+        }
+    }
+
+    #[test]
+    fn update_element() {
+        let mut tree = scattered_tree();
+        tree.update(5, Vec2::new(5.0, 3.0), Vec2::new(-30.0, -30.0));
+
+        assert_eq!(tree.len(), 8);
+        assert_eq!(tree.position(&5), Some(Vec2::new(-30.0, -30.0)));
+        assert!(tree.query_radius(Vec2::new(5.0, 3.0), 1.0).is_empty());
+        assert_eq!(
+            tree.query_radius(Vec2::new(-30.0, -30.0), 1.0),
+            vec![(Vec2::new(-30.0, -30.0), 5)]
+        );
+    }
+
+    #[test]
+    fn update_with_a_stale_position() {
+        let mut tree = scattered_tree();
+        tree.update(5, Vec2::new(-40.0, 40.0), Vec2::new(-30.0, -30.0));
+
+        assert_eq!(tree.len(), 8);
+        assert!(tree.query_radius(Vec2::new(5.0, 3.0), 1.0).is_empty());
+        assert_eq!(tree.query_range(&tree.boundary()).len(), 8);
+    }
+
+    #[test]
+    fn update_outside_boundary_drops_element() {
+        let mut tree = scattered_tree();
+        tree.update(5, Vec2::new(5.0, 3.0), Vec2::new(500.0, 3.0));
+
+        assert_eq!(tree.len(), 7);
+        assert_eq!(tree.position(&5), None);
+        assert_eq!(tree.query_range(&tree.boundary()).len(), 7);
+    }
+
+    #[test]
+    fn reinserting_moves_element() {
+        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
+        tree.insert(Vec2::new(-20.0, 10.0), 0);
+        tree.insert(Vec2::new(20.0, 10.0), 0);
+
+        assert_eq!(tree.len(), 1);
+        assert_eq!(
+            tree.query_range(&tree.boundary()),
+            vec![(Vec2::new(20.0, 10.0), 0)]
+        );
+    }
+
+    #[test]
+    fn remove_by_id() {
+        let mut tree = scattered_tree();
+
+        assert_eq!(tree.remove_by_id(&6), Some(Vec2::new(-41.0, 37.0)));
+        assert_eq!(tree.remove_by_id(&6), None);
+        assert_eq!(tree.len(), 7);
+        assert!(tree
+            .query_range(&tree.boundary())
+            .iter()
+            .all(|(_, id)| *id != 6));
+    }
+
+    #[test]
+    fn removing_collapses_underfull_nodes() {
+        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
+        tree.insert(Vec2::new(-20.0, 10.0), 0);
+        tree.insert(Vec2::new(20.0, 10.0), 1);
+        tree.insert(Vec2::new(-20.0, -10.0), 2);
+        tree.insert(Vec2::new(20.0, -10.0), 3);
+        tree.insert(Vec2::new(22.0, 10.0), 4);
+        assert!(matches!(tree.root.data, TreeData::Node(_)));
+
+        tree.remove_by_id(&0);
+        tree.remove_by_id(&1);
+        assert!(matches!(tree.root.data, TreeData::Node(_)));
+
+        tree.remove_by_id(&2);
+        if let TreeData::Leaf(data) = &tree.root.data {
+            assert_eq!(data.len(), 2);
+        } else {
+            unreachable!()
+        }
+
+        tree.remove_by_id(&3);
+        tree.remove_by_id(&4);
+        assert_eq!(tree.root.data, TreeData::Empty);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn collapse_is_recursive() {
+        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
+        for i in 0..5 {
+            let offset = 10.0 + i as f32;
+            tree.insert(Vec2::new(offset, offset), i);
+        }
+        tree.insert(Vec2::new(-30.0, -30.0), 5);
+
+        for i in 0..4 {
+            tree.remove_by_id(&i);
+        }
+        if let TreeData::Leaf(data) = &tree.root.data {
+            assert_eq!(data.len(), 2);
+        } else {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn many_updates_match_rebuild() {
+        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(100.0, 100.0)));
+        let mut positions = (0..200)
+            .map(|i| Vec2::new((i * 37 % 190) as f32 - 95.0, (i * 53 % 190) as f32 - 95.0))
+            .collect::<Vec<_>>();
+        for (i, position) in positions.iter().enumerate() {
+            tree.insert(*position, i);
+        }
+
+        for step in 0..20 {
+            for (i, position) in positions.iter_mut().enumerate() {
+                let old = *position;
+                let offset =
+                    Vec2::new(((i + step) % 7) as f32 - 3.0, ((i * step) % 5) as f32 - 2.0);
+                *position = (old + offset).clamp(Vec2::splat(-99.0), Vec2::splat(99.0));
+                tree.update(i, old, *position);
+            }
+        }
+
+        let mut rebuilt = QuadTree::new(tree.boundary());
+        for (i, position) in positions.iter().enumerate() {
+            rebuilt.insert(*position, i);
+        }
+
+        let center = Vec2::new(10.0, -20.0);
+        let mut found = tree
+            .query_radius(center, 40.0)
+            .iter()
+            .map(|(_, id)| *id)
+            .collect::<Vec<_>>();
+        let mut expected = rebuilt
+            .query_radius(center, 40.0)
+            .iter()
+            .map(|(_, id)| *id)
+            .collect::<Vec<_>>();
+        found.sort_unstable();
+        found.dedup();
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(found, expected);
+        assert_eq!(tree.len(), 200);
+    }
+
+    fn scattered_grid() -> SpatialHashGrid<usize> {
+        let mut grid = SpatialHashGrid::new(10.0);
+        for (point, id) in scattered_tree().query_range(&AABB::new(Vec2::ZERO, Vec2::splat(50.0))) {
+            SpatialIndex::insert(&mut grid, point, id);
+        }
+        grid
+    }
+
+    fn sorted_ids(found: Vec<(Vec2, usize)>) -> Vec<usize> {
+        let mut ids = found.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    #[test]
+    fn grid_matches_quadtree() {
+        let tree = scattered_tree();
+        let grid = scattered_grid();
+        assert_eq!(SpatialIndex::len(&grid), 8);
+
+        for center in [
+            Vec2::new(3.0, -7.0),
+            Vec2::new(20.0, 10.0),
+            Vec2::new(-45.0, 45.0),
+        ] {
+            for radius in [0.0, 5.0, 25.0, 60.0] {
+                assert_eq!(
+                    sorted_ids(SpatialIndex::query_radius(&grid, center, radius)),
+                    sorted_ids(tree.query_radius(center, radius))
+                );
+            }
+
+            let range = AABB::new(center, Vec2::new(15.0, 30.0));
+            assert_eq!(
+                sorted_ids(SpatialIndex::query_range(&grid, &range)),
+                sorted_ids(tree.query_range(&range))
+            );
+
+            for k in [1, 3, 8, 20] {
+                assert_eq!(
+                    SpatialIndex::k_nearest(&grid, center, k),
+                    tree.k_nearest(center, k)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn grid_update_and_remove() {
+        let mut grid = scattered_grid();
+
+        // Within the same cell
+        grid.update(5, Vec2::new(5.0, 3.0), Vec2::new(6.0, 4.0));
+        // Across cells
+        grid.update(1, Vec2::new(20.0, 10.0), Vec2::new(-80.0, 70.0));
+        // From where the caller wrongly thinks it is
+        grid.update(2, Vec2::new(90.0, 90.0), Vec2::new(-21.0, -11.0));
+
+        assert_eq!(
+            SpatialIndex::query_radius(&grid, Vec2::new(6.0, 4.0), 0.5),
+            vec![(Vec2::new(6.0, 4.0), 5)]
+        );
+        assert_eq!(
+            SpatialIndex::query_radius(&grid, Vec2::new(-80.0, 70.0), 0.5),
+            vec![(Vec2::new(-80.0, 70.0), 1)]
+        );
+        assert!(SpatialIndex::query_radius(&grid, Vec2::new(20.0, 10.0), 0.5).is_empty());
+        assert_eq!(
+            SpatialIndex::query_radius(&grid, Vec2::new(-20.0, -10.0), 1.5),
+            vec![(Vec2::new(-21.0, -11.0), 2)]
+        );
+
+        assert_eq!(grid.remove_by_id(&1), Some(Vec2::new(-80.0, 70.0)));
+        SpatialIndex::remove(&mut grid, Vec2::new(6.0, 4.0));
+        assert_eq!(SpatialIndex::len(&grid), 6);
+        assert!(SpatialIndex::query_radius(&grid, Vec2::ZERO, 200.0)
+            .iter()
+            .all(|(_, id)| *id != 1 && *id != 5));
+    }
+
+    #[test]
+    fn grid_handles_negative_coordinates() {
+        let mut grid = SpatialHashGrid::new(10.0);
+        SpatialIndex::insert(&mut grid, Vec2::new(-0.5, -0.5), 0);
+        SpatialIndex::insert(&mut grid, Vec2::new(0.5, 0.5), 1);
+
+        assert_eq!(grid.cell(Vec2::new(-0.5, -0.5)), [-1, -1, 0]);
+        assert_eq!(grid.cell(Vec2::new(0.5, 0.5)), [0, 0, 0]);
+        assert_eq!(
+            sorted_ids(SpatialIndex::query_radius(&grid, Vec2::ZERO, 1.0)),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn grid_k_nearest_far_from_every_point() {
+        let grid = scattered_grid();
+        let brute_force = |point: Vec2| {
+            let mut all = SpatialIndex::query_radius(&grid, Vec2::ZERO, 200.0);
+            all.sort_by(|(a, _), (b, _)| {
+                a.distance_squared(point)
+                    .total_cmp(&b.distance_squared(point))
+            });
+            all.truncate(3);
+            all
+        };
+
+        for point in [
+            Vec2::new(5000.0, -300.0),
+            Vec2::new(-1e12, 1e12),
+            Vec2::new(f32::MAX, f32::MIN),
+        ] {
+            let found = SpatialIndex::k_nearest(&grid, point, 3);
+            assert_eq!(found.len(), 3);
+            if point.length() < 1e6 {
+                assert_eq!(found, brute_force(point));
+            }
+        }
+    }
+
+    #[test]
+    fn cells_follow_subdivision() {
+        let mut tree = QuadTree::new(AABB::new(Vec2::ZERO, Vec2::splat(50.0)));
+        tree.insert(Vec2::new(10.0, 10.0), 0);
+        assert_eq!(tree.cells(), vec![tree.boundary()]);
+        assert_eq!(tree.depth(), 0);
+
+        for (id, point) in [(-10.0, 10.0), (-10.0, -10.0), (10.0, -10.0), (20.0, 20.0)]
+            .into_iter()
+            .enumerate()
+        {
+            tree.insert(Vec2::new(point.0, point.1), id + 1);
+        }
+        let cells = tree.cells();
+        assert_eq!(cells.len(), 4);
+        assert!(cells
+            .iter()
+            .all(|cell| cell.half_dimension == Vec2::splat(25.0)));
+        assert_eq!(tree.depth(), 1);
+
+        let mut grid = SpatialHashGrid::new(10.0);
+        SpatialIndex::insert(&mut grid, Vec2::new(-0.5, -0.5), 0);
+        SpatialIndex::insert(&mut grid, Vec2::new(0.5, 0.5), 1);
+        let mut centers = SpatialIndex::cells(&grid)
+            .into_iter()
+            .map(|cell| cell.center)
+            .collect::<Vec<_>>();
+        centers.sort_by(|a, b| a.x.total_cmp(&b.x));
+        assert_eq!(centers, vec![Vec2::splat(-5.0), Vec2::splat(5.0)]);
+        assert_eq!(SpatialIndex::depth(&grid), 0);
+    }
+
+    #[test]
+    fn indices_are_interchangeable() {
+        let mut indices: Vec<Box<dyn SpatialIndex<usize>>> = vec![
+            Box::new(QuadTree::new(AABB::new(Vec2::ZERO, Vec2::splat(50.0)))),
+            Box::new(SpatialHashGrid::new(10.0)),
+        ];
+
+        for index in &mut indices {
+            index.insert(Vec2::new(1.0, 1.0), 0);
+            index.insert(Vec2::new(30.0, 30.0), 1);
+            index.update(0, Vec2::new(1.0, 1.0), Vec2::new(28.0, 29.0));
+
+            assert_eq!(index.len(), 2);
+            assert_eq!(
+                sorted_ids(index.query_radius(Vec2::new(29.0, 29.0), 2.0)),
+                vec![0, 1]
+            );
+            assert_eq!(
+                index.k_nearest(Vec2::ZERO, 1),
+                vec![(Vec2::new(28.0, 29.0), 0)]
+            );
+        }
+    }
+
+    #[test]
+    fn points_on_subdivision_lines_are_stored_once() {
+        let mut tree = QuadTree::new(AABB::new(Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0)));
+        let points = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.0, 20.0),
+            Vec2::new(-25.0, 0.0),
+            Vec2::new(25.0, 25.0),
+            Vec2::new(50.0, 50.0),
+            Vec2::new(-50.0, -50.0),
+        ];
+        for (i, point) in points.iter().enumerate() {
+            tree.insert(*point, i);
+        }
+
+        let res = tree.query_range(&tree.boundary());
+        assert_eq!(res.len(), points.len());
+        assert_eq!(sorted_ids(res), (0..points.len()).collect::<Vec<_>>());
+        assert_eq!(tree.query_radius(Vec2::ZERO, 0.0), vec![(Vec2::ZERO, 0)]);
+    }
+
+    #[test]
+    fn wrap_offsets_cover_crossed_edges() {
+        let world = AABB::new(Vec2::ZERO, Vec2::new(100.0, 50.0));
+
+        let inside = AABB::new(Vec2::ZERO, Vec2::splat(10.0));
+        assert_eq!(wrap_offsets(&inside, &world), vec![Vec2::ZERO]);
+
+        let corner = AABB::new(Vec2::new(95.0, -45.0), Vec2::splat(10.0));
+        let mut offsets = wrap_offsets(&corner, &world);
+        offsets.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+        assert_eq!(
+            offsets,
+            vec![
+                Vec2::new(-200.0, 0.0),
+                Vec2::new(-200.0, 100.0),
+                Vec2::new(0.0, 0.0),
+                Vec2::new(0.0, 100.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn wrapped_query_finds_neighbours_across_edges() {
+        let world = AABB::new(Vec2::ZERO, Vec2::new(100.0, 50.0));
+        let mut tree = QuadTree::new(world);
+        tree.insert(Vec2::new(-98.0, 0.0), 0);
+        tree.insert(Vec2::new(97.0, 48.0), 1);
+        tree.insert(Vec2::new(0.0, 0.0), 2);
+
+        let res =
+            tree.query_range_wrapped(&AABB::new(Vec2::new(99.0, 0.0), Vec2::splat(5.0)), &world);
+        assert_eq!(res, vec![(Vec2::new(102.0, 0.0), 0)]);
+
+        let res = tree.query_radius_wrapped(Vec2::new(-99.0, -49.0), 5.0, &world);
+        assert_eq!(res, vec![(Vec2::new(-103.0, -52.0), 1)]);
+    }
+
+    /// Random points, a good share of them snapped onto the subdivision lines.
+    fn random_points(rng: &mut StdRng, count: usize, world: &AABB) -> Vec<Vec2> {
+        let min = world.center - world.half_dimension;
+        let max = world.center + world.half_dimension;
+        (0..count)
+            .map(|_| {
+                let point = Vec2::new(rng.gen_range(min.x..max.x), rng.gen_range(min.y..max.y));
+                if rng.gen_bool(0.3) {
+                    (point / 12.5).round() * 12.5
+                } else {
+                    point
+                }
+            })
+            .map(|point| point.clamp(min, max - Vec2::splat(0.001)))
+            .collect()
+    }
+
+    /// Offset from `from` to `to` going the short way around the torus.
+    fn toroidal_offset(from: Vec2, to: Vec2, world: &AABB) -> Vec2 {
+        let size = world.half_dimension * 2.0;
+        let offset = to - from;
+        offset - size * (offset / size).round()
+    }
+
+    fn property_indices(world: &AABB) -> Vec<Box<dyn SpatialIndex<usize>>> {
+        vec![
+            Box::new(QuadTree::new(*world)),
+            Box::new(QuadTree::with_config(*world, 1, 4)),
+            Box::new(SpatialHashGrid::new(12.5)),
+        ]
+    }
+
+    #[test]
+    fn property_no_duplicates() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let world = AABB::new(Vec2::ZERO, Vec2::splat(100.0));
+
+        for _ in 0..20 {
+            let points = random_points(&mut rng, 300, &world);
+            for mut index in property_indices(&world) {
+                for (i, point) in points.iter().enumerate() {
+                    index.insert(*point, i);
+                }
+
+                let all = index.query_range(&world);
+                assert_eq!(all.len(), points.len());
+                assert_eq!(sorted_ids(all), (0..points.len()).collect::<Vec<_>>());
+
+                let center = points[rng.gen_range(0..points.len())];
+                let radius = rng.gen_range(0.0..60.0);
+                let found = index.query_radius_wrapped(center, radius, &world);
+                assert_eq!(sorted_ids(found.clone()).len(), found.len());
+            }
+        }
+    }
+
+    #[test]
+    fn property_no_missed_neighbours() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let world = AABB::new(Vec2::new(10.0, -20.0), Vec2::new(100.0, 60.0));
+
+        for _ in 0..20 {
+            let points = random_points(&mut rng, 300, &world);
+            for mut index in property_indices(&world) {
+                for (i, point) in points.iter().enumerate() {
+                    index.insert(*point, i);
+                }
+
+                for _ in 0..10 {
+                    let center = random_points(&mut rng, 1, &world)[0];
+                    let radius = rng.gen_range(0.0..50.0);
+
+                    let expected = points
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, p)| toroidal_offset(center, **p, &world).length() <= radius)
+                        .map(|(i, _)| i)
+                        .collect::<Vec<_>>();
+                    let found = index.query_radius_wrapped(center, radius, &world);
+
+                    assert_eq!(found.len(), expected.len());
+                    assert_eq!(sorted_ids(found.clone()), expected);
+                    for (p, id) in found {
+                        let offset = toroidal_offset(center, points[id], &world);
+                        assert!((p - (center + offset)).length() < 1e-3);
+                    }
+
+                    let range = AABB::new(center, Vec2::new(radius, radius / 2.0));
+                    let expected = points
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, p)| {
+                            let offset = toroidal_offset(center, **p, &world).abs();
+                            offset.cmple(range.half_dimension).all()
+                        })
+                        .map(|(i, _)| i)
+                        .collect::<Vec<_>>();
+                    let found = index.query_range_wrapped(&range, &world);
+                    assert_eq!(found.len(), expected.len());
+                    assert_eq!(sorted_ids(found), expected);
+
+                    let mut by_distance = points
+                        .iter()
+                        .map(|p| toroidal_offset(center, *p, &world).length())
+                        .collect::<Vec<_>>();
+                    by_distance.sort_by(f32::total_cmp);
+                    let nearest = index.k_nearest_wrapped(center, 5, &world);
+                    assert_eq!(nearest.len(), 5);
+                    for ((p, id), expected) in nearest.into_iter().zip(by_distance) {
+                        let offset = toroidal_offset(center, points[id], &world);
+                        assert!((p - (center + offset)).length() < 1e-3);
+                        assert!((offset.length() - expected).abs() < 1e-3);
+                    }
+                }
+            }
+        }
+    }
+
+    fn random_points_3d(rng: &mut StdRng, count: usize, world: &AABB<Vec3>) -> Vec<Vec3> {
+        let (min, max) = (world.min(), world.max());
+        (0..count)
+            .map(|_| {
+                let point = Vec3::new(
+                    rng.gen_range(min.x..max.x),
+                    rng.gen_range(min.y..max.y),
+                    rng.gen_range(min.z..max.z),
+                );
+                // Plenty of points right on the subdivision planes
+                if rng.gen_bool(0.3) {
+                    (point / 12.5).round() * 12.5
+                } else {
+                    point
+                }
+            })
+            .map(|point| point.clamp(min, max - Vec3::splat(0.001)))
+            .collect()
+    }
+
+    fn sorted_ids_3d(found: &[(Vec3, usize)]) -> Vec<usize> {
+        let mut ids = found.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn octree_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let world = AABB::new(Vec3::ZERO, Vec3::splat(100.0));
+        let points = random_points_3d(&mut rng, 500, &world);
+
+        let mut tree = Octree::with_config(world, 2, 6);
+        for (i, point) in points.iter().enumerate() {
+            tree.insert(*point, i);
+        }
+        assert_eq!(tree.len(), points.len());
+        assert_eq!(
+            sorted_ids_3d(&tree.query_range(&world)),
+            (0..points.len()).collect::<Vec<_>>()
+        );
+
+        for _ in 0..20 {
+            let center = random_points_3d(&mut rng, 1, &world)[0];
+            let radius = rng.gen_range(0.0..60.0);
+
+            let found = tree.query_radius(center, radius);
+            let expected = (0..points.len())
+                .filter(|i| points[*i].distance(center) <= radius)
+                .collect::<Vec<_>>();
+            assert_eq!(sorted_ids_3d(&found), expected);
+            assert!(found
+                .windows(2)
+                .all(|pair| pair[0].0.distance(center) <= pair[1].0.distance(center)));
+
+            let range = AABB::new(center, Vec3::splat(radius));
+            let expected = (0..points.len())
+                .filter(|i| range.contains(points[*i]))
+                .collect::<Vec<_>>();
+            assert_eq!(sorted_ids_3d(&tree.query_range(&range)), expected);
+
+            let nearest = tree.k_nearest(center, 5);
+            let mut by_distance = points.clone();
+            by_distance.sort_by(|a, b| a.distance(center).total_cmp(&b.distance(center)));
+            for ((found, _), expected) in nearest.iter().zip(&by_distance) {
+                assert_eq!(found.distance(center), expected.distance(center));
+            }
+        }
+    }
+
+    #[test]
+    fn grid_in_3d_matches_the_octree() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let world = AABB::new(Vec3::ZERO, Vec3::splat(100.0));
+        let points = random_points_3d(&mut rng, 300, &world);
+
+        let mut tree = Octree::new(world);
+        let mut grid = SpatialHashGrid::new(15.0);
+        for (i, point) in points.iter().enumerate() {
+            tree.insert(*point, i);
+            SpatialIndex::insert(&mut grid, *point, i);
+        }
+
+        let queries = AABB::new(Vec3::ZERO, Vec3::splat(300.0));
+        for center in random_points_3d(&mut rng, 20, &queries) {
+            let radius = rng.gen_range(0.0..60.0);
+            assert_eq!(
+                sorted_ids_3d(&SpatialIndex::query_radius(&grid, center, radius)),
+                sorted_ids_3d(&tree.query_radius(center, radius))
+            );
+            let range = AABB::new(center, Vec3::new(radius, 20.0, 40.0));
+            assert_eq!(
+                sorted_ids_3d(&SpatialIndex::query_range(&grid, &range)),
+                sorted_ids_3d(&tree.query_range(&range))
+            );
+            assert_eq!(
+                SpatialIndex::k_nearest(&grid, center, 7),
+                tree.k_nearest(center, 7)
+            );
+        }
+    }
+
+    #[test]
+    fn octree_updates_in_place_and_collapses() {
+        let world = AABB::new(Vec3::ZERO, Vec3::splat(8.0));
+        let mut tree = Octree::with_config(world, 2, 8);
+
+        for i in 0..8 {
+            let side = |bit: usize| if i & bit != 0 { 4.0 } else { -4.0 };
+            tree.insert(Vec3::new(side(1), side(2), side(4)), i);
+        }
+        assert!(matches!(tree.root.data, TreeData::Node(_)));
+
+        tree.update(3, Vec3::new(4.0, 4.0, -4.0), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(tree.position(&3), Some(Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(tree.query_radius(Vec3::new(1.0, 2.0, 3.0), 0.1).len(), 1);
+
+        for i in 0..7 {
+            tree.remove_by_id(&i);
+        }
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.root.data, TreeData::Leaf(vec![(Vec3::splat(4.0), 7)]));
+    }
+
+    #[test]
+    fn octree_wrapped_queries_cross_every_face() {
+        let world = AABB::new(Vec3::ZERO, Vec3::splat(50.0));
+        let mut tree = Octree::new(world);
+        tree.insert(Vec3::new(49.0, 49.0, 49.0), 0);
+        tree.insert(Vec3::new(0.0, 0.0, 0.0), 1);
+
+        let found = tree.query_radius_wrapped(Vec3::splat(-49.0), 5.0, &world);
+        assert_eq!(found, vec![(Vec3::splat(-51.0), 0)]);
+    }
 }
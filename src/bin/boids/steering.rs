@@ -0,0 +1,610 @@
+use super::*;
+use boids::Surroundings;
+use config::BoidsConfig;
+
+/// What a single boid sees when deciding where to go.
+pub struct Neighborhood<'a, V: Vector = Vec2> {
+    pub position: V,
+    pub velocity: V,
+    /// Neighbours of the same species found by the spatial partition, without
+    /// the boid itself.
+    pub positions: &'a [V],
+    pub velocities: &'a [V],
+    /// Positions of neighbours from species this one avoids.
+    pub strangers: &'a [V],
+    /// Positions of neighbours this one hunts.
+    pub prey: &'a [V],
+    /// Positions of neighbours hunting this one.
+    pub predators: &'a [V],
+    pub surroundings: &'a Surroundings<V>,
+}
+
+/// A rule turning what a boid sees into a steering force.
+///
+/// New behaviours only need to implement this and be added to a `Steering`.
+pub trait SteeringBehavior<V: Vector = Vec2>: Send + Sync {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V;
+}
+
+/// Weighted behaviours a flock follows, their forces are summed.
+#[derive(Resource, Default)]
+pub struct Steering<V: Vector = Vec2> {
+    behaviors: Vec<(f32, Box<dyn SteeringBehavior<V>>)>,
+    /// How many of the first behaviours were built from the config, the ones
+    /// added after them are kept when the config changes.
+    configured: usize,
+}
+
+impl<V: Vector> Steering<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, weight: f32, behavior: impl SteeringBehavior<V> + 'static) -> Self {
+        self.behaviors.push((weight, Box::new(behavior)));
+        self
+    }
+
+    /// The classic rules, the reactions to other species and the reactions to
+    /// the surroundings switched on in `config`, weighted as configured.
+    pub fn from_config(config: &FlockingConfig) -> Self {
+        let mut steering = Steering::new()
+            .with(config.separation_weight, Separation)
+            .with(config.alignment_weight, Alignment)
+            .with(config.cohesion_weight, Cohesion)
+            .with(config.avoid_species_weight, AvoidStrangers)
+            .with(config.hunt_weight, Hunt)
+            .with(config.flee_weight, FleePredators);
+
+        if config.avoid_obstacles {
+            steering = steering
+                .with(config.avoidance_weight, AvoidObstacles)
+                .with(config.avoidance_weight, AvoidWalls);
+        }
+        if config.seek_targets {
+            steering = steering.with(config.seek_weight, SeekTargets);
+        }
+        if config.flee_threats {
+            steering = steering.with(config.flee_weight, FleeThreats);
+        }
+
+        steering.configured = steering.behaviors.len();
+        steering
+    }
+
+    /// Replaces the behaviours built from the previous config with the ones of
+    /// `config`, keeping the behaviours added since.
+    pub fn configure(&mut self, config: &FlockingConfig) {
+        let added = self.behaviors.split_off(self.configured);
+        *self = Steering::from_config(config);
+        self.behaviors.extend(added);
+    }
+
+    pub fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        self.behaviors
+            .iter()
+            .map(|(weight, behavior)| behavior.steer(boid, config) * *weight)
+            .sum()
+    }
+}
+
+/// The `Steering` of every species, in the order of `BoidsConfig::species`.
+#[derive(Resource, Default)]
+pub struct FlockSteering<V: Vector = Vec2>(pub Vec<Steering<V>>);
+
+impl<V: Vector> FlockSteering<V> {
+    pub fn from_config(config: &BoidsConfig) -> Self {
+        let mut steering = Self::default();
+        steering.configure(config);
+        steering
+    }
+
+    /// Follows a new config, behaviours added to a species are kept as long as
+    /// the species is.
+    pub fn configure(&mut self, config: &BoidsConfig) {
+        self.0.resize_with(config.species.len(), Steering::new);
+        for (steering, species) in self.0.iter_mut().zip(&config.species) {
+            steering.configure(&species.flocking);
+        }
+    }
+}
+
+/// Steering force turning `velocity` towards `target` at full speed.
+pub fn seek<V: Vector>(target: V, position: V, velocity: V, config: &FlockingConfig) -> V {
+    let desired = target - position;
+    let desired = desired.normalize() * config.max_speed;
+
+    let mut steer = desired - velocity;
+
+    if steer.length() > config.max_force {
+        steer = steer.normalize();
+        steer *= config.max_force;
+    }
+    steer
+}
+
+/// Keeps `separation_radius` away from neighbours.
+pub struct Separation;
+
+impl<V: Vector> SteeringBehavior<V> for Separation {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        separate(boid.position, boid.velocity, boid.positions, config)
+    }
+}
+
+/// Keeps `separation_radius` away from species this one avoids.
+pub struct AvoidStrangers;
+
+impl<V: Vector> SteeringBehavior<V> for AvoidStrangers {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        separate(boid.position, boid.velocity, boid.strangers, config)
+    }
+}
+
+fn separate<V: Vector>(position: V, velocity: V, others: &[V], config: &FlockingConfig) -> V {
+    let desired_seperation = config.separation_radius;
+
+    let mut steer = V::ZERO;
+    let mut count = 0;
+    for other in others {
+        let dist = (position - *other).length();
+        if dist > 0.0 && dist < desired_seperation {
+            let diff = position - *other;
+            let diff = diff.normalize();
+            let diff = diff / dist;
+            steer += diff;
+            count += 1;
+        }
+    }
+
+    if count > 0 {
+        steer /= count as f32;
+    }
+
+    if steer.length() > 0.0 {
+        steer = steer.normalize();
+        steer *= config.max_speed;
+        steer -= velocity;
+        if steer.length() > config.max_force {
+            steer = steer.normalize();
+            steer *= config.max_force;
+        }
+    }
+
+    steer
+}
+
+/// Matches the heading of neighbours within `alignment_radius`.
+pub struct Alignment;
+
+impl<V: Vector> SteeringBehavior<V> for Alignment {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        let neighbor_dist = config.alignment_radius;
+
+        let mut sum = V::ZERO;
+        let mut count = 0;
+
+        for (position, velocity) in boid.positions.iter().zip(boid.velocities) {
+            let dist = (boid.position - *position).length();
+            if dist > 0.0 && dist < neighbor_dist {
+                sum += *velocity;
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            sum /= count as f32;
+
+            sum = sum.normalize();
+            sum *= config.max_speed;
+            let mut steer = sum - boid.velocity;
+            if steer.length() > config.max_force {
+                steer = steer.normalize();
+                steer *= config.max_force;
+            }
+            steer
+        } else {
+            V::ZERO
+        }
+    }
+}
+
+/// Heads for the centre of neighbours within `cohesion_radius`.
+pub struct Cohesion;
+
+impl<V: Vector> SteeringBehavior<V> for Cohesion {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        let neighbor_dist = config.cohesion_radius;
+
+        let mut sum = V::ZERO;
+        let mut count = 0;
+
+        for position in boid.positions {
+            let dist = (boid.position - *position).length();
+            if dist > 0.0 && dist < neighbor_dist {
+                sum += *position;
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            sum /= count as f32;
+            seek(sum, boid.position, boid.velocity, config)
+        } else {
+            V::ZERO
+        }
+    }
+}
+
+/// Heads for a fixed point.
+pub struct Seek<V: Vector = Vec2> {
+    pub target: V,
+}
+
+impl<V: Vector> SteeringBehavior<V> for Seek<V> {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        if self.target == boid.position {
+            return V::ZERO;
+        }
+        seek(self.target, boid.position, boid.velocity, config)
+    }
+}
+
+/// Steers away from the closest point of every obstacle within
+/// `avoidance_radius`, harder the closer it is.
+pub struct AvoidObstacles;
+
+impl<V: Vector> SteeringBehavior<V> for AvoidObstacles {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        let mut steer = V::ZERO;
+
+        for (center, obstacle) in &boid.surroundings.obstacles {
+            let closest = obstacle.closest_point(*center, boid.position);
+            let dist = (boid.position - closest).length();
+
+            if dist == 0.0 {
+                // Inside the obstacle, head out the shortest way we know of
+                steer += (boid.position - *center).normalize_or_zero() * 2.0;
+            } else if dist < config.avoidance_radius {
+                steer += (boid.position - closest).normalize() / dist * config.avoidance_radius;
+            }
+        }
+
+        if steer.length() > 0.0 {
+            steer = steer.normalize() * config.max_speed - boid.velocity;
+            steer = steer.clamp_length_max(config.max_force);
+        }
+
+        steer
+    }
+}
+
+/// Turns back from the walls of the world within `avoidance_radius`, harder the
+/// closer they are. Does nothing unless the surroundings have walls.
+pub struct AvoidWalls;
+
+impl<V: Vector> SteeringBehavior<V> for AvoidWalls {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        let walls = match &boid.surroundings.walls {
+            Some(walls) => walls,
+            None => return V::ZERO,
+        };
+
+        let mut steer = V::ZERO;
+        for axis in 0..V::DIM {
+            let to_min = boid.position.component(axis) - walls.min().component(axis);
+            let to_max = walls.max().component(axis) - boid.position.component(axis);
+
+            if to_min < config.avoidance_radius {
+                steer += V::axis(axis) * (1.0 - to_min.max(0.0) / config.avoidance_radius);
+            }
+            if to_max < config.avoidance_radius {
+                steer -= V::axis(axis) * (1.0 - to_max.max(0.0) / config.avoidance_radius);
+            }
+        }
+
+        if steer.length() > 0.0 {
+            steer = steer.normalize() * config.max_speed - boid.velocity;
+            steer = steer.clamp_length_max(config.max_force);
+        }
+
+        steer
+    }
+}
+
+/// Seeks the closest `Target`.
+pub struct SeekTargets;
+
+impl<V: Vector> SteeringBehavior<V> for SeekTargets {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        seek_closest(boid, &boid.surroundings.targets, config)
+    }
+}
+
+/// Chases the closest prey in sight.
+pub struct Hunt;
+
+impl<V: Vector> SteeringBehavior<V> for Hunt {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        seek_closest(boid, boid.prey, config)
+    }
+}
+
+fn seek_closest<V: Vector>(boid: &Neighborhood<V>, targets: &[V], config: &FlockingConfig) -> V {
+    targets
+        .iter()
+        .min_by(|a, b| {
+            a.distance_squared(boid.position)
+                .total_cmp(&b.distance_squared(boid.position))
+        })
+        .map_or(V::ZERO, |target| {
+            Seek { target: *target }.steer(boid, config)
+        })
+}
+
+/// Steers directly away from every `Threat` within `flee_radius`.
+pub struct FleeThreats;
+
+impl<V: Vector> SteeringBehavior<V> for FleeThreats {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        flee(boid, &boid.surroundings.threats, config)
+    }
+}
+
+/// Steers directly away from every predator within `flee_radius`.
+pub struct FleePredators;
+
+impl<V: Vector> SteeringBehavior<V> for FleePredators {
+    fn steer(&self, boid: &Neighborhood<V>, config: &FlockingConfig) -> V {
+        flee(boid, boid.predators, config)
+    }
+}
+
+fn flee<V: Vector>(boid: &Neighborhood<V>, threats: &[V], config: &FlockingConfig) -> V {
+    let mut away = V::ZERO;
+
+    for threat in threats {
+        let dist = (boid.position - *threat).length();
+        if dist > 0.0 && dist < config.flee_radius {
+            away += (boid.position - *threat).normalize();
+        }
+    }
+
+    if away.length() > 0.0 {
+        let desired = away.normalize() * config.max_speed;
+        (desired - boid.velocity).clamp_length_max(config.max_force)
+    } else {
+        V::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boids::Obstacle;
+
+    fn alone<'a>(
+        position: Vec2,
+        velocity: Vec2,
+        surroundings: &'a Surroundings,
+    ) -> Neighborhood<'a> {
+        Neighborhood {
+            position,
+            velocity,
+            positions: &[],
+            velocities: &[],
+            strangers: &[],
+            prey: &[],
+            predators: &[],
+            surroundings,
+        }
+    }
+
+    #[test]
+    fn separation_pushes_apart() {
+        let config = FlockingConfig::default();
+        let surroundings = Surroundings::default();
+        let boid = Neighborhood {
+            positions: &[Vec2::new(10.0, 0.0)],
+            velocities: &[Vec2::ZERO],
+            ..alone(Vec2::ZERO, Vec2::ZERO, &surroundings)
+        };
+
+        let steer = Separation.steer(&boid, &config);
+        assert!(steer.x < 0.0);
+        assert_eq!(steer.y, 0.0);
+    }
+
+    #[test]
+    fn alignment_and_cohesion_ignore_far_neighbours() {
+        let config = FlockingConfig::default();
+        let surroundings = Surroundings::default();
+        let boid = Neighborhood {
+            positions: &[Vec2::new(config.neighbor_radius() * 2.0, 0.0)],
+            velocities: &[Vec2::new(0.0, 100.0)],
+            ..alone(Vec2::ZERO, Vec2::ZERO, &surroundings)
+        };
+
+        assert_eq!(Alignment.steer(&boid, &config), Vec2::ZERO);
+        assert_eq!(Cohesion.steer(&boid, &config), Vec2::ZERO);
+    }
+
+    #[test]
+    fn weights_scale_behaviours() {
+        let config = FlockingConfig::default();
+        let surroundings = Surroundings::default();
+        let boid = alone(Vec2::ZERO, Vec2::ZERO, &surroundings);
+        let target = Vec2::new(0.0, 100.0);
+
+        let once = Steering::new().with(1.0, Seek { target });
+        let twice = Steering::new()
+            .with(1.0, Seek { target })
+            .with(0.5, Seek { target })
+            .with(0.5, Seek { target });
+
+        assert!(once.steer(&boid, &config).y > 0.0);
+        assert_eq!(
+            once.steer(&boid, &config) * 2.0,
+            twice.steer(&boid, &config)
+        );
+        assert_eq!(Steering::new().steer(&boid, &config), Vec2::ZERO);
+    }
+
+    #[test]
+    fn added_behaviours_outlive_config_changes() {
+        let mut config = FlockingConfig {
+            separation_weight: 0.0,
+            alignment_weight: 0.0,
+            cohesion_weight: 0.0,
+            ..Default::default()
+        };
+        let surroundings = Surroundings::default();
+        let boid = alone(Vec2::ZERO, Vec2::ZERO, &surroundings);
+        let target = Vec2::new(0.0, 100.0);
+
+        let mut steering = Steering::from_config(&config).with(1.0, Seek { target });
+        let seeking = steering.steer(&boid, &config);
+        assert!(seeking.y > 0.0);
+
+        config.avoid_obstacles = false;
+        steering.configure(&config);
+        assert_eq!(steering.configured, 8);
+        assert_eq!(steering.behaviors.len(), 9);
+        assert_eq!(steering.steer(&boid, &config), seeking);
+    }
+
+    #[test]
+    fn avoids_obstacles_ahead() {
+        let config = FlockingConfig::default();
+        let surroundings = Surroundings {
+            obstacles: vec![(Vec2::ZERO, Obstacle::Circle { radius: 10.0 })],
+            ..Default::default()
+        };
+        let boid = alone(Vec2::new(-20.0, 0.0), Vec2::new(100.0, 0.0), &surroundings);
+
+        let steer = AvoidObstacles.steer(&boid, &config);
+        assert!(steer.x < 0.0);
+        assert!(steer.length() <= config.max_force);
+    }
+
+    #[test]
+    fn turns_back_from_walls() {
+        let config = FlockingConfig::default();
+        let walls = AABB::new(Vec2::ZERO, Vec2::splat(100.0));
+        let surroundings = Surroundings {
+            walls: Some(walls),
+            ..Default::default()
+        };
+
+        let boid = alone(Vec2::new(90.0, 0.0), Vec2::new(100.0, 0.0), &surroundings);
+        let steer = AvoidWalls.steer(&boid, &config);
+        assert!(steer.x < 0.0);
+        assert_eq!(steer.y, 0.0);
+        assert!(steer.length() <= config.max_force);
+
+        let boid = alone(Vec2::ZERO, Vec2::new(100.0, 0.0), &surroundings);
+        assert_eq!(AvoidWalls.steer(&boid, &config), Vec2::ZERO);
+
+        let surroundings = Surroundings::default();
+        let boid = alone(Vec2::new(90.0, 0.0), Vec2::new(100.0, 0.0), &surroundings);
+        assert_eq!(AvoidWalls.steer(&boid, &config), Vec2::ZERO);
+    }
+
+    #[test]
+    fn seeks_closest_target() {
+        let config = FlockingConfig::default();
+        let surroundings = Surroundings {
+            targets: vec![Vec2::new(0.0, 500.0), Vec2::new(0.0, -50.0)],
+            ..Default::default()
+        };
+        let boid = alone(Vec2::ZERO, Vec2::ZERO, &surroundings);
+
+        let steer = SeekTargets.steer(&boid, &config);
+        assert!(steer.y < 0.0);
+        assert_eq!(steer.x, 0.0);
+    }
+
+    #[test]
+    fn flees_threats_in_range() {
+        let config = FlockingConfig::default();
+        let surroundings = Surroundings {
+            threats: vec![Vec2::new(50.0, 0.0)],
+            ..Default::default()
+        };
+        let boid = alone(Vec2::ZERO, Vec2::ZERO, &surroundings);
+        assert!(FleeThreats.steer(&boid, &config).x < 0.0);
+
+        let surroundings = Surroundings {
+            threats: vec![Vec2::new(config.flee_radius * 2.0, 0.0)],
+            ..Default::default()
+        };
+        let boid = alone(Vec2::ZERO, Vec2::ZERO, &surroundings);
+        assert_eq!(FleeThreats.steer(&boid, &config), Vec2::ZERO);
+    }
+
+    #[test]
+    fn predators_chase_and_prey_flees() {
+        let config = FlockingConfig::default();
+        let surroundings = Surroundings::default();
+
+        let hawk = Neighborhood {
+            prey: &[Vec2::new(90.0, 0.0), Vec2::new(0.0, 30.0)],
+            ..alone(Vec2::ZERO, Vec2::ZERO, &surroundings)
+        };
+        let steer = Hunt.steer(&hawk, &config);
+        assert!(steer.y > 0.0);
+        assert_eq!(steer.x, 0.0);
+
+        let sparrow = Neighborhood {
+            predators: &[Vec2::new(0.0, 30.0)],
+            ..alone(Vec2::ZERO, Vec2::ZERO, &surroundings)
+        };
+        assert!(FleePredators.steer(&sparrow, &config).y < 0.0);
+
+        // Neither reacts to its own kind
+        let flock = Neighborhood {
+            positions: &[Vec2::new(0.0, 30.0)],
+            velocities: &[Vec2::ZERO],
+            ..alone(Vec2::ZERO, Vec2::ZERO, &surroundings)
+        };
+        assert_eq!(Hunt.steer(&flock, &config), Vec2::ZERO);
+        assert_eq!(FleePredators.steer(&flock, &config), Vec2::ZERO);
+    }
+
+    #[test]
+    fn strangers_are_kept_at_a_distance() {
+        let config = FlockingConfig::default();
+        let surroundings = Surroundings::default();
+        let boid = Neighborhood {
+            strangers: &[Vec2::new(10.0, 0.0)],
+            ..alone(Vec2::ZERO, Vec2::ZERO, &surroundings)
+        };
+
+        assert!(AvoidStrangers.steer(&boid, &config).x < 0.0);
+        assert_eq!(Separation.steer(&boid, &config), Vec2::ZERO);
+    }
+
+    #[test]
+    fn disabled_rules_are_left_out() {
+        let config = FlockingConfig {
+            separation_weight: 0.0,
+            alignment_weight: 0.0,
+            cohesion_weight: 0.0,
+            avoid_obstacles: false,
+            seek_targets: false,
+            flee_threats: false,
+            ..Default::default()
+        };
+        let surroundings = Surroundings {
+            obstacles: vec![(Vec2::new(15.0, 0.0), Obstacle::Circle { radius: 10.0 })],
+            targets: vec![Vec2::new(0.0, 100.0)],
+            threats: vec![Vec2::new(-50.0, 0.0)],
+            walls: Some(AABB::new(Vec2::ZERO, Vec2::splat(20.0))),
+        };
+        let boid = alone(Vec2::ZERO, Vec2::new(10.0, 0.0), &surroundings);
+
+        let steering = Steering::from_config(&config);
+        assert_eq!(steering.behaviors.len(), 6);
+        assert_eq!(steering.steer(&boid, &config), Vec2::ZERO);
+    }
+}
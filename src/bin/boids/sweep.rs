@@ -0,0 +1,377 @@
+//! Parameter sweeps run without a window or a renderer, with `--sweep <path>`
+//! on the command line.
+//!
+//! Every combination of a grid of flocking parameters is run from the same seed
+//! for the same number of steps, and the metrics of the flock it ends up as are
+//! written as CSV, one row per combination.
+
+use super::*;
+use analysis::FlockMetrics;
+use anyhow::{anyhow, Context};
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+use serde::Deserialize;
+use std::io::Write;
+
+/// A sweep, loaded from a RON file like `assets/boids/sweep.ron`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Sweep {
+    /// Flock every combination starts from, as in `flocking.ron`.
+    pub flock: BoidsConfig,
+    pub seed: u64,
+    /// Steps run before the flock is measured.
+    pub ticks: usize,
+    /// Half the width and height of the world.
+    pub world_half_size: (f32, f32),
+    pub edges: Edges,
+    /// Spatial partitions every combination is run with.
+    pub indices: Vec<Index>,
+    /// Values tried for each parameter, named as in `FlockingConfig`. A plain
+    /// name sets the parameter of every species, `species.name` of one only.
+    pub parameters: Vec<(String, Vec<f32>)>,
+}
+
+impl Default for Sweep {
+    fn default() -> Self {
+        Self {
+            flock: BoidsConfig::default(),
+            seed: 0,
+            ticks: 600,
+            world_half_size: (640.0, 360.0),
+            edges: Edges::Wrap,
+            indices: vec![Index::Tree],
+            parameters: Vec::new(),
+        }
+    }
+}
+
+impl Sweep {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let ron =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+        ron::from_str(&ron).with_context(|| format!("Failed to parse {}", path))
+    }
+
+    /// Every combination of parameter values, in the order of `parameters`,
+    /// the last one changing fastest.
+    pub fn combinations(&self) -> Vec<Vec<f32>> {
+        self.parameters
+            .iter()
+            .fold(vec![Vec::new()], |combinations, (_, values)| {
+                combinations
+                    .iter()
+                    .flat_map(|combination| {
+                        values.iter().map(move |value| {
+                            let mut combination = combination.clone();
+                            combination.push(*value);
+                            combination
+                        })
+                    })
+                    .collect()
+            })
+    }
+
+    /// The flock with the parameters set to `values`, in the order of
+    /// `parameters`.
+    pub fn config(&self, values: &[f32]) -> anyhow::Result<BoidsConfig> {
+        let mut config = self.flock.clone();
+
+        for ((name, _), value) in self.parameters.iter().zip(values) {
+            let (species, parameter) = match name.split_once('.') {
+                Some((species, parameter)) => (Some(species), parameter),
+                None => (None, name.as_str()),
+            };
+
+            let mut found = false;
+            for flock_species in &mut config.species {
+                if species.is_none() || species == Some(flock_species.name.as_str()) {
+                    *flock_species
+                        .flocking
+                        .parameter_mut(parameter)
+                        .ok_or_else(|| anyhow!("unknown parameter \"{}\"", parameter))? = *value;
+                    found = true;
+                }
+            }
+            if !found {
+                return Err(anyhow!("no species for parameter \"{}\"", name));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Runs every combination with every index, writing a row of metrics to
+    /// `out` for each.
+    pub fn run(&self, out: &mut impl Write) -> anyhow::Result<()> {
+        let combinations = self.combinations();
+        let configs = combinations
+            .iter()
+            .map(|values| self.config(values))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // The grid's cells are as wide as the neighbourhood, so it can't index
+        // a flock that has none
+        if self.indices.contains(&Index::Grid) {
+            if let Some(values) = combinations
+                .iter()
+                .zip(&configs)
+                .find(|(_, config)| config.neighbor_radius() <= 0.0)
+                .map(|(values, _)| values)
+            {
+                return Err(anyhow!(
+                    "combination {:?} has no neighbourhood to size the grid from",
+                    values
+                ));
+            }
+        }
+
+        let world_bounds = AABB::new(
+            Vec2::ZERO,
+            Vec2::new(self.world_half_size.0, self.world_half_size.1),
+        );
+
+        let names = self.parameters.iter().map(|(name, _)| name.as_str());
+        let header = names
+            .chain([
+                "index",
+                "count",
+                "polarization",
+                "mean_nearest_distance",
+                "clusters",
+            ])
+            .collect::<Vec<_>>();
+        writeln!(out, "{}", header.join(","))?;
+
+        let runs = combinations.len() * self.indices.len();
+        for (run, ((values, config), index)) in combinations
+            .iter()
+            .zip(&configs)
+            .flat_map(|combination| self.indices.iter().map(move |index| (combination, *index)))
+            .enumerate()
+        {
+            eprintln!("Sweep run {}/{}: {:?} {:?}", run + 1, runs, values, index);
+
+            let metrics = simulate(
+                config,
+                self.seed,
+                index,
+                WorldBounds::new(world_bounds, self.edges),
+                self.ticks,
+            );
+
+            let row = values
+                .iter()
+                .map(f32::to_string)
+                .chain([
+                    format!("{:?}", index),
+                    metrics.count.to_string(),
+                    metrics.polarization.to_string(),
+                    metrics.mean_nearest_distance.to_string(),
+                    metrics.clusters.to_string(),
+                ])
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", row.join(","))?;
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Spawns `flock` into `world` and indexes it with `partition`.
+pub fn spawn_flock<V: Vector>(
+    world: &mut World,
+    flock: Vec<BoidBundle<V>>,
+    mut partition: BoidsPartition<V>,
+) {
+    // Set up by the app's plugins otherwise
+    ComputeTaskPool::init(TaskPool::default);
+
+    for boid in flock {
+        let position = boid.position.0;
+        let entity = world.spawn(boid).id();
+        partition.insert(position, entity);
+    }
+    world.insert_resource(partition);
+}
+
+/// Steps `flock` `ticks` times in a world of its own, without an app, in
+/// `world_bounds` indexed by `partition`. There are no obstacles, targets or
+/// threats.
+pub fn run_flock<V: Vector>(
+    flock: Vec<BoidBundle<V>>,
+    config: &BoidsConfig,
+    partition: BoidsPartition<V>,
+    world_bounds: WorldBounds<V>,
+    ticks: usize,
+) -> World {
+    let mut world = World::new();
+    world.insert_resource(config.clone());
+    world.insert_resource(world_bounds);
+    world.insert_resource(FlockSteering::<V>::from_config(config));
+    spawn_flock(&mut world, flock, partition);
+
+    let mut stage = SystemStage::single_threaded().with_system_set(flock_systems::<V>());
+    for _ in 0..ticks {
+        stage.run(&mut world);
+    }
+    world
+}
+
+/// Sets `config`'s flock up from `seed`, indexed with `index`, steps it `ticks`
+/// times with `run_flock` and measures it.
+pub fn simulate(
+    config: &BoidsConfig,
+    seed: u64,
+    index: Index,
+    world_bounds: WorldBounds,
+    ticks: usize,
+) -> FlockMetrics {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut flock = boids::random_flock::<Vec2>(&mut rng, config);
+    for boid in &mut flock {
+        contain_boid(&world_bounds, boid);
+    }
+    let partition = BoidsPartition::new(index, world_bounds.aabb, config.neighbor_radius());
+    let mut world = run_flock(flock, config, partition, world_bounds, ticks);
+
+    let boids = world
+        .query_filtered::<(Entity, &Position, &Velocity), With<Boid>>()
+        .iter(&world)
+        .map(|(entity, position, velocity)| (entity, position.0, velocity.0))
+        .collect::<Vec<_>>();
+    FlockMetrics::measure(
+        &boids,
+        world.resource::<BoidsPartition>().0.as_ref(),
+        world.resource::<WorldBounds>(),
+        config.neighbor_radius(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(parameters: &[(&str, &[f32])]) -> Sweep {
+        Sweep {
+            flock: BoidsConfig {
+                species: vec![
+                    config::Species {
+                        name: "sparrow".to_string(),
+                        count: 30,
+                        ..Default::default()
+                    },
+                    config::Species {
+                        name: "hawk".to_string(),
+                        count: 2,
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            },
+            ticks: 20,
+            parameters: parameters
+                .iter()
+                .map(|(name, values)| (name.to_string(), values.to_vec()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn shipped_sweep_parses() {
+        let sweep: Sweep = ron::from_str(include_str!("../../../assets/boids/sweep.ron")).unwrap();
+        assert!(!sweep.parameters.is_empty());
+        for values in sweep.combinations() {
+            sweep.config(&values).unwrap();
+        }
+    }
+
+    #[test]
+    fn every_combination_once() {
+        let sweep = sweep(&[
+            ("max_speed", &[1.0, 2.0]),
+            ("alignment_weight", &[0.0, 0.5, 1.0]),
+        ]);
+        assert_eq!(
+            sweep.combinations(),
+            vec![
+                vec![1.0, 0.0],
+                vec![1.0, 0.5],
+                vec![1.0, 1.0],
+                vec![2.0, 0.0],
+                vec![2.0, 0.5],
+                vec![2.0, 1.0],
+            ]
+        );
+        assert_eq!(Sweep::default().combinations(), vec![Vec::<f32>::new()]);
+    }
+
+    #[test]
+    fn parameters_set_every_species_or_one() {
+        let sweep = sweep(&[("max_force", &[1.0]), ("hawk.max_speed", &[2.0])]);
+        let config = sweep.config(&[1.0, 2.0]).unwrap();
+
+        let [sparrow, hawk] = [&config.species[0].flocking, &config.species[1].flocking];
+        assert_eq!((sparrow.max_force, hawk.max_force), (1.0, 1.0));
+        assert_eq!(sparrow.max_speed, FlockingConfig::default().max_speed);
+        assert_eq!(hawk.max_speed, 2.0);
+
+        assert!(self::sweep(&[("max_sped", &[1.0])]).config(&[1.0]).is_err());
+        assert!(self::sweep(&[("crow.max_speed", &[1.0])])
+            .config(&[1.0])
+            .is_err());
+    }
+
+    #[test]
+    fn one_row_per_combination() {
+        let sweep = sweep(&[("separation_radius", &[15.0, 25.0])]);
+        let mut out = Vec::new();
+        sweep.run(&mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let rows = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            rows[0],
+            "separation_radius,index,count,polarization,mean_nearest_distance,clusters"
+        );
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("15,Tree,32,"));
+        assert!(rows[2].starts_with("25,Tree,32,"));
+
+        // Same seed, same results
+        let mut again = Vec::new();
+        sweep.run(&mut again).unwrap();
+        assert_eq!(out, String::from_utf8(again).unwrap());
+    }
+
+    #[test]
+    fn one_row_per_index() {
+        let mut sweep = sweep(&[("separation_radius", &[15.0])]);
+        sweep.indices = vec![Index::Tree, Index::Grid];
+        let mut out = Vec::new();
+        sweep.run(&mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let rows = out.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("15,Tree,32,"));
+        assert!(rows[2].starts_with("15,Grid,32,"));
+    }
+
+    #[test]
+    fn grid_needs_a_neighbourhood() {
+        let mut sweep = sweep(&[
+            ("separation_radius", &[0.0]),
+            ("alignment_radius", &[0.0]),
+            ("cohesion_radius", &[0.0, 10.0]),
+        ]);
+        sweep.indices = vec![Index::Tree, Index::Grid];
+        let mut out = Vec::new();
+        assert!(sweep.run(&mut out).is_err());
+        // Nothing is run before the sweep is turned down
+        assert!(out.is_empty());
+
+        sweep.indices = vec![Index::Tree];
+        sweep.run(&mut out).unwrap();
+    }
+}
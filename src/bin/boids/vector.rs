@@ -0,0 +1,163 @@
+use bevy::prelude::{Vec2, Vec3};
+use std::fmt::Debug;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// Operations the flocking rules and spatial partitions need from a position,
+/// so the same code runs in 2D with `Vec2` and in 3D with `Vec3`.
+pub trait Vector:
+    Copy
+    + Debug
+    + Default
+    + PartialEq
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<f32, Output = Self>
+    + Div<f32, Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign<f32>
+    + DivAssign<f32>
+    + Sum
+{
+    const ZERO: Self;
+    /// Number of axes.
+    const DIM: usize;
+
+    fn splat(value: f32) -> Self;
+    /// Unit vector along `axis`.
+    fn axis(axis: usize) -> Self;
+    fn component(self, axis: usize) -> f32;
+
+    fn length(self) -> f32;
+    fn length_squared(self) -> f32;
+    fn distance_squared(self, other: Self) -> f32;
+    fn normalize(self) -> Self;
+    fn normalize_or_zero(self) -> Self;
+    fn clamp_length_max(self, max: f32) -> Self;
+    fn lerp(self, other: Self, t: f32) -> Self;
+
+    // Per component
+    fn abs(self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn clamp(self, min: Self, max: Self) -> Self;
+
+    /// Drops the axes of a translation this vector doesn't have.
+    fn from_translation(translation: Vec3) -> Self;
+    /// Translation to render at, missing axes are zero.
+    fn to_translation(self) -> Vec3;
+}
+
+macro_rules! impl_vector {
+    ($type:ty, $dim:expr, $from_translation:expr, $to_translation:expr) => {
+        impl Vector for $type {
+            const ZERO: Self = <$type>::ZERO;
+            const DIM: usize = $dim;
+
+            fn splat(value: f32) -> Self {
+                <$type>::splat(value)
+            }
+
+            fn axis(axis: usize) -> Self {
+                let mut unit = <$type>::ZERO;
+                unit[axis] = 1.0;
+                unit
+            }
+
+            fn component(self, axis: usize) -> f32 {
+                self[axis]
+            }
+
+            fn length(self) -> f32 {
+                <$type>::length(self)
+            }
+
+            fn length_squared(self) -> f32 {
+                <$type>::length_squared(self)
+            }
+
+            fn distance_squared(self, other: Self) -> f32 {
+                <$type>::distance_squared(self, other)
+            }
+
+            fn normalize(self) -> Self {
+                <$type>::normalize(self)
+            }
+
+            fn normalize_or_zero(self) -> Self {
+                <$type>::normalize_or_zero(self)
+            }
+
+            fn clamp_length_max(self, max: f32) -> Self {
+                <$type>::clamp_length_max(self, max)
+            }
+
+            fn lerp(self, other: Self, t: f32) -> Self {
+                <$type>::lerp(self, other, t)
+            }
+
+            fn abs(self) -> Self {
+                <$type>::abs(self)
+            }
+
+            fn max(self, other: Self) -> Self {
+                <$type>::max(self, other)
+            }
+
+            fn clamp(self, min: Self, max: Self) -> Self {
+                <$type>::clamp(self, min, max)
+            }
+
+            fn from_translation(translation: Vec3) -> Self {
+                $from_translation(translation)
+            }
+
+            fn to_translation(self) -> Vec3 {
+                $to_translation(self)
+            }
+        }
+    };
+}
+
+impl_vector!(Vec2, 2, Vec3::truncate, |v: Vec2| v.extend(0.0));
+impl_vector!(Vec3, 3, |v: Vec3| v, |v: Vec3| v);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spread<V: Vector>(points: &[V]) -> f32 {
+        let center = points.iter().copied().sum::<V>() / points.len() as f32;
+        points
+            .iter()
+            .map(|point| (*point - center).length())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn same_code_in_2d_and_3d() {
+        let flat = [Vec2::new(-1.0, 0.0), Vec2::new(3.0, 0.0)];
+        let deep = [Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 3.0)];
+        assert_eq!(spread(&flat), 2.0);
+        assert_eq!(spread(&deep), 2.0);
+    }
+
+    #[test]
+    fn axes_and_translations() {
+        assert_eq!(<Vec2 as Vector>::axis(1), Vec2::Y);
+        assert_eq!(<Vec3 as Vector>::axis(2), Vec3::Z);
+        assert_eq!(Vector::component(Vec3::new(1.0, 2.0, 3.0), 2), 3.0);
+
+        let translation = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(Vec2::from_translation(translation), Vec2::new(1.0, 2.0));
+        assert_eq!(
+            Vector::to_translation(Vec2::new(1.0, 2.0)),
+            Vec3::new(1.0, 2.0, 0.0)
+        );
+        assert_eq!(<Vec3 as Vector>::from_translation(translation), translation);
+    }
+}
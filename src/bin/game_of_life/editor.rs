@@ -0,0 +1,97 @@
+//! Editing the universe by hand: painting cells with the mouse, pausing, stepping,
+//! jumping ahead, changing the speed, clearing and randomising.
+
+use super::*;
+use std::time::Duration;
+
+/// Shortest and longest time between two generations, in seconds.
+const MIN_INTERVAL: f32 = 0.01;
+const MAX_INTERVAL: f32 = 2.0;
+
+/// Jumps with J go `2^k` generations ahead at once.
+#[derive(Resource)]
+pub struct JumpSize(pub u32);
+
+impl Default for JumpSize {
+    fn default() -> Self {
+        Self(10)
+    }
+}
+
+/// Left click paints live cells, right click erases them, both can be dragged.
+pub fn paint_system(
+    mouse_buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    mut game: ResMut<Game>,
+) {
+    let state = if mouse_buttons.pressed(MouseButton::Left) {
+        CellState::Alive
+    } else if mouse_buttons.pressed(MouseButton::Right) {
+        CellState::Dead
+    } else {
+        return;
+    };
+
+    let (camera, projection) = match cameras.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    if let Some(GridCoordinate { x, y }) = view::cursor_cell(&windows, camera, projection) {
+        // Only touch the game when a cell changes, so it isn't redrawn for nothing
+        if game.get(x, y) != state {
+            game.set(x, y, state);
+        }
+    }
+}
+
+/// Space pauses and resumes, N steps one generation, J jumps `2^k` generations
+/// and [ and ] change `k`, + and - speed up and slow down, C clears the
+/// universe and R fills the middle of the view at random.
+pub fn controls_system(
+    keys: Res<Input<KeyCode>>,
+    cameras: Query<&Transform, With<Camera>>,
+    mut update_timer: ResMut<UpdateTimer>,
+    mut jump_size: ResMut<JumpSize>,
+    mut game: ResMut<Game>,
+) {
+    let timer = &mut update_timer.0;
+
+    if keys.just_pressed(KeyCode::Space) {
+        if timer.paused() {
+            timer.unpause();
+        } else {
+            timer.pause();
+        }
+    }
+    if keys.just_pressed(KeyCode::N) {
+        game.step();
+    }
+    if keys.just_pressed(KeyCode::J) {
+        let k = jump_size.0.min(game.max_jump());
+        game.step_pow2(k);
+    }
+    if keys.just_pressed(KeyCode::LBracket) {
+        jump_size.0 = jump_size.0.saturating_sub(1);
+    }
+    if keys.just_pressed(KeyCode::RBracket) {
+        jump_size.0 = (jump_size.0 + 1).min(game.max_jump());
+    }
+
+    let interval = timer.duration().as_secs_f32();
+    if keys.any_just_pressed([KeyCode::Equals, KeyCode::NumpadAdd]) {
+        timer.set_duration(Duration::from_secs_f32((interval / 2.0).max(MIN_INTERVAL)));
+    }
+    if keys.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        timer.set_duration(Duration::from_secs_f32((interval * 2.0).min(MAX_INTERVAL)));
+    }
+
+    if keys.just_pressed(KeyCode::C) {
+        game.set_universe(&Universe::new());
+    }
+    if keys.just_pressed(KeyCode::R) {
+        if let Ok(camera) = cameras.get_single() {
+            randomize(game.0.as_mut(), view::view_center(camera), soup_size());
+        }
+    }
+}
@@ -0,0 +1,618 @@
+//! Hashlife, independent of Bevy: the universe as a quadtree whose identical
+//! subtrees are shared, with the future of every subtree memoised. Patterns
+//! that repeat themselves in space or in time can then be stepped by millions of
+//! generations at once.
+//!
+//! See Gosper, "Exploiting regularities in large cellular spaces" (1984).
+
+use super::life::{CellState, Cells, Rule};
+use super::universe::{ChunkRange, Engine, Universe, CHUNK_SIZE};
+use std::collections::HashMap;
+
+/// Index of a node in `HashLife::nodes`.
+type NodeId = u32;
+
+const DEAD: NodeId = 0;
+const ALIVE: NodeId = 1;
+
+/// Level of the smallest root, 8 by 8 cells.
+const MIN_ROOT_LEVEL: u8 = 3;
+
+/// Nodes kept before the ones the root no longer uses are dropped.
+const MAX_NODES: usize = 1 << 22;
+
+/// Largest jump, `2^MAX_JUMP` generations.
+const MAX_JUMP: u32 = 40;
+
+/// A square of `2^level` cells on each side. Leaves are single cells, the
+/// other nodes are made of four nodes one level down.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    level: u8,
+    /// North west, north east, south west and south east quarters.
+    children: [NodeId; 4],
+    population: u64,
+}
+
+/// Steps a universe following a `Rule` with the Hashlife algorithm, in jumps of
+/// any power of two generations.
+#[derive(Debug, Clone)]
+pub struct HashLife {
+    rule: Rule,
+    nodes: Vec<Node>,
+    /// Node made of each set of four children, so every square of cells is
+    /// stored once.
+    joined: HashMap<[NodeId; 4], NodeId>,
+    /// Empty node of each level.
+    empty: Vec<NodeId>,
+    /// Centre of a node `2^j` generations later, for each node and `j` tried.
+    successors: HashMap<(NodeId, u8), NodeId>,
+    /// Centred on the origin.
+    root: NodeId,
+    generation: u64,
+}
+
+impl HashLife {
+    pub fn new(rule: Rule) -> Self {
+        let leaf = |population| Node {
+            level: 0,
+            children: [DEAD; 4],
+            population,
+        };
+        let mut life = Self {
+            rule,
+            nodes: vec![leaf(0), leaf(1)],
+            joined: HashMap::new(),
+            empty: vec![DEAD],
+            successors: HashMap::new(),
+            root: DEAD,
+            generation: 0,
+        };
+        assert!(life.supports(&rule), "Hashlife can't run {}", rule);
+        life.root = life.empty(MIN_ROOT_LEVEL);
+        life
+    }
+
+    pub fn from_universe(rule: Rule, universe: &Universe) -> Self {
+        let mut life = Self::new(rule);
+        life.set_universe(universe);
+        life
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id as usize]
+    }
+
+    fn join(&mut self, children: [NodeId; 4]) -> NodeId {
+        if let Some(id) = self.joined.get(&children) {
+            return *id;
+        }
+
+        let node = Node {
+            level: self.node(children[0]).level + 1,
+            children,
+            population: children.iter().map(|id| self.node(*id).population).sum(),
+        };
+        let id = self.nodes.len() as NodeId;
+        self.nodes.push(node);
+        self.joined.insert(children, id);
+        id
+    }
+
+    fn empty(&mut self, level: u8) -> NodeId {
+        while self.empty.len() <= level as usize {
+            let below = *self.empty.last().unwrap();
+            let empty = self.join([below; 4]);
+            self.empty.push(empty);
+        }
+        self.empty[level as usize]
+    }
+
+    /// Same node inside a node twice as large, surrounded by dead cells.
+    fn expand(&mut self, id: NodeId) -> NodeId {
+        let Node {
+            level,
+            children: [nw, ne, sw, se],
+            ..
+        } = *self.node(id);
+        let empty = self.empty(level - 1);
+        let children = [
+            self.join([empty, empty, empty, nw]),
+            self.join([empty, empty, ne, empty]),
+            self.join([empty, sw, empty, empty]),
+            self.join([se, empty, empty, empty]),
+        ];
+        self.join(children)
+    }
+
+    /// Node one level down made of the cells in the middle of `id`.
+    fn centre(&mut self, id: NodeId) -> NodeId {
+        let [nw, ne, sw, se] = self.node(id).children;
+        self.join([
+            self.node(nw).children[3],
+            self.node(ne).children[2],
+            self.node(sw).children[1],
+            self.node(se).children[0],
+        ])
+    }
+
+    /// Whether every live cell of the root is in its middle half, far enough
+    /// from the edges to step without reaching outside.
+    fn is_centred(&self) -> bool {
+        let root = self.node(self.root);
+        let [nw, ne, sw, se] = root.children;
+        let middle = [(nw, 3), (ne, 2), (sw, 1), (se, 0)]
+            .iter()
+            .map(|(child, corner)| {
+                let quarter = self.node(*child).children[*corner];
+                self.node(self.node(quarter).children[*corner]).population
+            })
+            .sum::<u64>();
+        middle == root.population
+    }
+
+    /// Whether `(x, y)` is in a node of `level` centred on the origin.
+    fn contains(level: u8, x: i64, y: i64) -> bool {
+        let half = 1i64 << (level - 1);
+        (-half..half).contains(&x) && (-half..half).contains(&y)
+    }
+
+    /// Cells of the root from its top left corner, `y` going down.
+    fn local(&self, x: i64, y: i64) -> (u64, u64) {
+        let half = 1i64 << (self.node(self.root).level - 1);
+        ((x + half) as u64, (half - 1 - y) as u64)
+    }
+
+    fn cell(&self, mut id: NodeId, mut x: u64, mut y: u64) -> CellState {
+        loop {
+            let node = self.node(id);
+            if node.population == 0 {
+                return CellState::Dead;
+            }
+            if node.level == 0 {
+                return CellState::Alive;
+            }
+            let half = 1u64 << (node.level - 1);
+            let quarter = (y >= half) as usize * 2 + (x >= half) as usize;
+            id = node.children[quarter];
+            x %= half;
+            y %= half;
+        }
+    }
+
+    fn with_cell(&mut self, id: NodeId, x: u64, y: u64, state: CellState) -> NodeId {
+        let node = *self.node(id);
+        if node.level == 0 {
+            return match state {
+                CellState::Alive => ALIVE,
+                CellState::Dead => DEAD,
+            };
+        }
+
+        let half = 1u64 << (node.level - 1);
+        let quarter = (y >= half) as usize * 2 + (x >= half) as usize;
+        let mut children = node.children;
+        children[quarter] = self.with_cell(children[quarter], x % half, y % half, state);
+        self.join(children)
+    }
+
+    /// Live cells of node `id`, whose top left corner is at `corner`, within
+    /// `min` and `max`.
+    fn collect_alive(
+        &self,
+        id: NodeId,
+        corner: (i64, i64),
+        (min, max): ((i64, i64), (i64, i64)),
+        alive: &mut Vec<(i64, i64)>,
+    ) {
+        let node = self.node(id);
+        let side = 1i64 << node.level;
+        let outside = corner.0 > max.0
+            || corner.0 + side <= min.0
+            || corner.1 < min.1
+            || corner.1 - side >= max.1;
+        if node.population == 0 || outside {
+            return;
+        }
+        if node.level == 0 {
+            alive.push(corner);
+            return;
+        }
+
+        let half = side / 2;
+        let [nw, ne, sw, se] = node.children;
+        self.collect_alive(nw, corner, (min, max), alive);
+        self.collect_alive(ne, (corner.0 + half, corner.1), (min, max), alive);
+        self.collect_alive(sw, (corner.0, corner.1 - half), (min, max), alive);
+        self.collect_alive(se, (corner.0 + half, corner.1 - half), (min, max), alive);
+    }
+
+    /// Live cells between the corners `min` and `max`, both included.
+    fn alive_in(&self, min: (i64, i64), max: (i64, i64)) -> Vec<(i64, i64)> {
+        let half = 1i64 << (self.node(self.root).level - 1);
+        let mut alive = Vec::new();
+        self.collect_alive(self.root, (-half, half - 1), (min, max), &mut alive);
+        alive
+    }
+
+    /// The 2 by 2 cells in the middle of a 4 by 4 node, one generation later.
+    fn step_base(&mut self, id: NodeId) -> NodeId {
+        let mut cells = [[CellState::Dead; 4]; 4];
+        for (y, row) in cells.iter_mut().enumerate() {
+            for (x, cell) in row.iter_mut().enumerate() {
+                *cell = self.cell(id, x as u64, y as u64);
+            }
+        }
+
+        let mut next = [DEAD; 4];
+        for (i, (x, y)) in [(1, 1), (2, 1), (1, 2), (2, 2)].into_iter().enumerate() {
+            // The neighbourhood has a range of 1, so stays inside the block
+            let neighbors = self
+                .rule
+                .neighborhood()
+                .offsets()
+                .filter(|(dx, dy)| {
+                    cells[(y as isize + dy) as usize][(x as isize + dx) as usize]
+                        == CellState::Alive
+                })
+                .count();
+            if self.rule.next(cells[y][x], neighbors) == CellState::Alive {
+                next[i] = ALIVE;
+            }
+        }
+        self.join(next)
+    }
+
+    /// The middle half of node `id`, of level `n`, `2^j` generations later,
+    /// with `j <= n - 2`.
+    fn successor(&mut self, id: NodeId, j: u8) -> NodeId {
+        let node = *self.node(id);
+        if node.population == 0 {
+            return self.empty(node.level - 1);
+        }
+        if node.level == 2 {
+            return self.step_base(id);
+        }
+        if let Some(next) = self.successors.get(&(id, j)) {
+            return *next;
+        }
+
+        // The nine overlapping nodes one level down, stepped
+        let [nw, ne, sw, se] = node.children;
+        let [a, b, c, d] = [nw, ne, sw, se].map(|child| self.node(child).children);
+        let overlapping = [
+            nw,
+            self.join([a[1], b[0], a[3], b[2]]),
+            ne,
+            self.join([a[2], a[3], c[0], c[1]]),
+            self.join([a[3], b[2], c[1], d[0]]),
+            self.join([b[2], b[3], d[0], d[1]]),
+            sw,
+            self.join([c[1], d[0], c[3], d[2]]),
+            se,
+        ];
+        let full_speed = j == node.level - 2;
+        let first = if full_speed { j - 1 } else { j };
+        let r = overlapping.map(|sub| self.successor(sub, first));
+
+        // Put back together as four nodes, stepped again at full speed or only
+        // cropped otherwise
+        let quarters = [
+            self.join([r[0], r[1], r[3], r[4]]),
+            self.join([r[1], r[2], r[4], r[5]]),
+            self.join([r[3], r[4], r[6], r[7]]),
+            self.join([r[4], r[5], r[7], r[8]]),
+        ];
+        let children = quarters.map(|quarter| {
+            if full_speed {
+                self.successor(quarter, j - 1)
+            } else {
+                self.centre(quarter)
+            }
+        });
+        let next = self.join(children);
+
+        self.successors.insert((id, j), next);
+        next
+    }
+
+    /// Drops the nodes the root doesn't use and every memoised step.
+    fn collect_garbage(&mut self) {
+        let mut kept = Self::new(self.rule);
+        kept.generation = self.generation;
+        let mut copied = HashMap::new();
+        kept.root = kept.copy(self, self.root, &mut copied);
+        *self = kept;
+    }
+
+    fn copy(
+        &mut self,
+        from: &HashLife,
+        id: NodeId,
+        copied: &mut HashMap<NodeId, NodeId>,
+    ) -> NodeId {
+        if id == DEAD || id == ALIVE {
+            return id;
+        }
+        if let Some(copy) = copied.get(&id) {
+            return *copy;
+        }
+        let children = from
+            .node(id)
+            .children
+            .map(|child| self.copy(from, child, copied));
+        let copy = self.join(children);
+        copied.insert(id, copy);
+        copy
+    }
+}
+
+impl Engine for HashLife {
+    fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    /// Memoised steps followed the old rule, they are forgotten.
+    fn set_rule(&mut self, rule: Rule) {
+        assert!(self.supports(&rule), "Hashlife can't run {}", rule);
+        if rule != self.rule {
+            self.rule = rule;
+            self.successors.clear();
+        }
+    }
+
+    /// Rules whose cells only see the cells next to them, and which leave
+    /// empty space empty. The centre of a node is then known `2^(level - 2)`
+    /// generations ahead.
+    fn supports(&self, rule: &Rule) -> bool {
+        rule.neighborhood().range() == 1 && !rule.births_from_nothing()
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn population(&self) -> u64 {
+        self.node(self.root).population
+    }
+
+    fn get(&self, x: i64, y: i64) -> CellState {
+        if !Self::contains(self.node(self.root).level, x, y) {
+            return CellState::Dead;
+        }
+        let (x, y) = self.local(x, y);
+        self.cell(self.root, x, y)
+    }
+
+    fn set(&mut self, x: i64, y: i64, state: CellState) {
+        while !Self::contains(self.node(self.root).level, x, y) {
+            self.root = self.expand(self.root);
+        }
+        let (x, y) = self.local(x, y);
+        self.root = self.with_cell(self.root, x, y, state);
+    }
+
+    fn universe(&self) -> Universe {
+        let half = 1i64 << (self.node(self.root).level - 1);
+        let mut universe = Universe::new();
+        for (x, y) in self.alive_in((-half, -half), (half - 1, half - 1)) {
+            universe.set(x, y, CellState::Alive);
+        }
+        universe
+    }
+
+    fn set_universe(&mut self, universe: &Universe) {
+        self.root = self.empty(MIN_ROOT_LEVEL);
+        for (x, y) in universe.alive() {
+            self.set(x, y, CellState::Alive);
+        }
+    }
+
+    fn chunks_in(&self, ((min_x, min_y), (max_x, max_y)): ChunkRange) -> Vec<((i64, i64), Cells)> {
+        let size = CHUNK_SIZE as i64;
+        let alive = self.alive_in(
+            (min_x * size, min_y * size),
+            ((max_x + 1) * size - 1, (max_y + 1) * size - 1),
+        );
+
+        let mut chunks = Universe::new();
+        for (x, y) in alive {
+            chunks.set(x, y, CellState::Alive);
+        }
+        chunks.chunks_in(((min_x, min_y), (max_x, max_y)))
+    }
+
+    fn step(&mut self) {
+        self.step_pow2(0);
+    }
+
+    fn max_jump(&self) -> u32 {
+        MAX_JUMP
+    }
+
+    fn step_pow2(&mut self, k: u32) {
+        let k = k as u8;
+        // Room for the pattern to spread at the speed of light, one cell a
+        // generation, without leaving the middle of the root
+        while self.node(self.root).level < k + 3 || !self.is_centred() {
+            self.root = self.expand(self.root);
+        }
+        self.root = self.successor(self.root, k);
+        self.generation += 1 << k;
+
+        if self.nodes.len() > MAX_NODES {
+            self.collect_garbage();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::life::{Life, NAMED_RULES};
+    use super::super::pattern::Pattern;
+    use super::super::universe::SparseLife;
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn pattern(rle: &str) -> Universe {
+        let mut universe = Universe::new();
+        let pattern = Pattern::from_rle(rle).unwrap();
+        for (x, y) in pattern.cells.alive() {
+            universe.set(x as i64, -(y as i64), CellState::Alive);
+        }
+        universe
+    }
+
+    fn sorted(mut alive: Vec<(i64, i64)>) -> Vec<(i64, i64)> {
+        alive.sort_by_key(|(x, y)| (*y, *x));
+        alive
+    }
+
+    #[test]
+    fn cells_anywhere() {
+        let mut life = HashLife::new(Rule::conway());
+        for (x, y) in [
+            (0, 0),
+            (-1, -1),
+            (3, -4),
+            (-1000, 250),
+            (1 << 40, -(1 << 40)),
+        ] {
+            life.set(x, y, CellState::Alive);
+            assert_eq!(life.get(x, y), CellState::Alive);
+        }
+        assert_eq!(life.get(1, 0), CellState::Dead);
+        assert_eq!(life.get(i64::MAX, i64::MIN), CellState::Dead);
+        assert_eq!(life.population(), 5);
+
+        life.set(3, -4, CellState::Dead);
+        assert_eq!(life.population(), 4);
+        let universe = life.universe();
+        assert_eq!(universe.population(), 4);
+        assert_eq!(
+            HashLife::from_universe(Rule::conway(), &universe).universe(),
+            universe
+        );
+
+        let chunks = life.chunks_in(((-1, -1), (0, 0)));
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|(_, cells)| cells.population())
+                .sum::<usize>(),
+            2
+        );
+    }
+
+    #[test]
+    fn agrees_with_the_naive_engine() {
+        let r_pentomino = "x = 3, y = 3\nb2o$2o$bo!";
+        let gun = include_str!("../../../assets/game_of_life/gosper_glider_gun.rle");
+        let (size, offset) = (200, 100);
+
+        for universe in [pattern(r_pentomino), pattern(gun)] {
+            let mut cells = Cells::new(size, size);
+            for (x, y) in universe.alive() {
+                cells.set(
+                    (x + offset) as usize,
+                    (y + offset) as usize,
+                    CellState::Alive,
+                );
+            }
+            let mut naive = Life::new(Rule::conway(), cells);
+            let mut hashlife = HashLife::from_universe(Rule::conway(), &universe);
+
+            // Long enough for the R-pentomino to throw its first gliders,
+            // which stay off the edges of the grid
+            for generation in 1..=300 {
+                naive.step();
+                hashlife.step();
+
+                let expected = naive
+                    .cells()
+                    .alive()
+                    .map(|(x, y)| (x as i64 - offset, y as i64 - offset))
+                    .collect::<Vec<_>>();
+                let alive = sorted(hashlife.universe().alive().collect());
+                assert_eq!(alive, expected, "generation {}", generation);
+            }
+            assert_eq!(hashlife.generation(), 300);
+        }
+    }
+
+    #[test]
+    fn jumps_agree_with_single_steps() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut soup = Universe::new();
+        for y in 0..24 {
+            for x in 0..24 {
+                if rng.gen_bool(0.4) {
+                    soup.set(x, y, CellState::Alive);
+                }
+            }
+        }
+
+        // Seeds grows too fast to go far, Bosco's rule sees too far for Hashlife
+        let rules = NAMED_RULES
+            .iter()
+            .filter(|(name, _)| !matches!(*name, "Seeds" | "Bosco"))
+            .map(|(_, rule)| *rule)
+            .chain(["B2/S013V"]);
+        for rule in rules {
+            let rule: Rule = rule.parse().unwrap();
+            let mut sparse = SparseLife::new(rule, soup.clone());
+            let mut hashlife = HashLife::from_universe(rule, &soup);
+            for k in [0, 2, 3, 1, 5] {
+                for _ in 0..1 << k {
+                    sparse.step();
+                }
+                hashlife.step_pow2(k);
+                assert_eq!(
+                    hashlife.universe(),
+                    sparse.universe(),
+                    "{} after 2^{}",
+                    rule,
+                    k
+                );
+            }
+            assert_eq!(hashlife.generation(), sparse.generation());
+        }
+    }
+
+    #[test]
+    fn millions_of_generations() {
+        let glider = pattern("x = 3, y = 3\nbo$2bo$3o!");
+        let mut life = HashLife::from_universe(Rule::conway(), &glider);
+
+        // A glider moves one cell diagonally every 4 generations
+        life.step_pow2(24);
+        assert_eq!(life.generation(), 1 << 24);
+        assert_eq!(life.population(), 5);
+        let shift = 1 << 22;
+        let moved = glider
+            .alive()
+            .map(|(x, y)| (x + shift, y - shift))
+            .collect::<Vec<_>>();
+        assert_eq!(sorted(life.universe().alive().collect()), sorted(moved));
+
+        // The gun keeps firing, a glider every 30 generations
+        let gun = include_str!("../../../assets/game_of_life/gosper_glider_gun.rle");
+        let mut life = HashLife::from_universe(Rule::conway(), &pattern(gun));
+        for _ in 0..7 {
+            life.step_pow2(1);
+        }
+        life.step_pow2(20);
+        let periods = (14 + (1 << 20)) / 30;
+        assert_eq!(life.generation(), periods * 30);
+        assert_eq!(life.population(), 36 + 5 * periods);
+    }
+
+    #[test]
+    fn b0_rules_only_run_on_grids() {
+        let rule = "B0123478/S34678".parse().unwrap();
+        assert!(!HashLife::new(Rule::conway()).supports(&rule));
+        assert!(!SparseLife::new(Rule::conway(), Universe::new()).supports(&rule));
+        assert!(Life::new(Rule::conway(), Cells::new(8, 8)).supports(&rule));
+    }
+}
@@ -0,0 +1,831 @@
+//! Life-like cellular automata, independent of Bevy: the grid of cells and how
+//! its edges join, the birth and survival rules over a neighbourhood, and the
+//! engine stepping one with the other.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CellState {
+    Alive,
+    #[default]
+    Dead,
+}
+
+/// A `width` by `height` grid of cells, everything outside it is dead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cells {
+    cells: Vec<CellState>,
+    width: usize,
+    height: usize,
+}
+
+impl Cells {
+    /// A grid of dead cells.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            cells: vec![CellState::Dead; width * height],
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: isize, y: isize) -> CellState {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            CellState::Dead
+        } else {
+            self.cells[y as usize * self.width + x as usize]
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, state: CellState) {
+        assert!(
+            x < self.width && y < self.height,
+            "({}, {}) is outside the grid",
+            x,
+            y
+        );
+        self.cells[y * self.width + x] = state;
+    }
+
+    /// Coordinates of the live cells, row by row.
+    pub fn alive(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| **state == CellState::Alive)
+            .map(|(i, _)| (i % self.width, i / self.width))
+    }
+
+    pub fn population(&self) -> usize {
+        self.alive().count()
+    }
+
+    /// Live cells at `offsets` from `(x, y)`, those of a `Neighborhood`, the
+    /// edges of the grid joined following `topology`.
+    pub fn neighbors(
+        &self,
+        x: usize,
+        y: usize,
+        offsets: &[(isize, isize)],
+        topology: Topology,
+    ) -> usize {
+        let (x, y) = (x as isize, y as isize);
+        let mut count = 0;
+        for (dx, dy) in offsets {
+            if let Some((x, y)) = topology.locate(x + dx, y + dy, self.width, self.height) {
+                if self.cells[y * self.width + x] == CellState::Alive {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+/// How the edges of a grid join. Past a bounded edge every cell is dead, a
+/// torus joins the opposite edges, a Klein bottle joins the top and bottom ones
+/// the wrong way round and a cross-surface joins both pairs the wrong way round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Topology {
+    #[default]
+    Bounded,
+    Torus,
+    KleinBottle,
+    CrossSurface,
+}
+
+impl Topology {
+    /// Cell of a `width` by `height` grid found at `(x, y)`, which can be past
+    /// its edges. `None` past a bounded edge.
+    pub fn locate(self, x: isize, y: isize, width: usize, height: usize) -> Option<(usize, usize)> {
+        let (width, height) = (width as isize, height as isize);
+        let inside = (0..width).contains(&x) && (0..height).contains(&y);
+        if inside || self == Topology::Bounded {
+            return inside.then_some((x as usize, y as usize));
+        }
+
+        // An edge joined the wrong way round mirrors the cells past it, once
+        // every time it is crossed
+        let (across_x, across_y) = (x.div_euclid(width), y.div_euclid(height));
+        let (mut x, mut y) = (x.rem_euclid(width), y.rem_euclid(height));
+        let twisted =
+            |times: isize, topologies: &[Topology]| times % 2 != 0 && topologies.contains(&self);
+        if twisted(across_y, &[Topology::KleinBottle, Topology::CrossSurface]) {
+            x = width - 1 - x;
+        }
+        if twisted(across_x, &[Topology::CrossSurface]) {
+            y = height - 1 - y;
+        }
+        Some((x as usize, y as usize))
+    }
+}
+
+/// Largest range of a neighbourhood, so that a cell counts at most 225 cells.
+pub const MAX_RANGE: u8 = 7;
+
+/// Cells around a cell whose states decide its next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighborhood {
+    /// The square of cells up to the range away, diagonals included.
+    Moore(u8),
+    /// The diamond of cells up to the range away in steps along the axes.
+    VonNeumann(u8),
+}
+
+impl Neighborhood {
+    pub fn range(self) -> u8 {
+        match self {
+            Neighborhood::Moore(range) | Neighborhood::VonNeumann(range) => range,
+        }
+    }
+
+    /// Offsets from a cell to its neighbours, row by row, the cell itself left
+    /// out.
+    pub fn offsets(self) -> impl Iterator<Item = (isize, isize)> {
+        let range = self.range() as isize;
+        (-range..=range)
+            .flat_map(move |dy| (-range..=range).map(move |dx| (dx, dy)))
+            .filter(move |(dx, dy)| {
+                (*dx, *dy) != (0, 0)
+                    && match self {
+                        Neighborhood::Moore(_) => true,
+                        Neighborhood::VonNeumann(_) => dx.abs() + dy.abs() <= range,
+                    }
+            })
+    }
+
+    /// Number of neighbours of a cell.
+    pub fn size(self) -> usize {
+        self.offsets().count()
+    }
+}
+
+/// A set of neighbour counts, up to 255.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Counts([u64; 4]);
+
+impl Counts {
+    fn insert(&mut self, count: usize) {
+        self.0[count / 64] |= 1 << (count % 64);
+    }
+
+    fn contains(&self, count: usize) -> bool {
+        count < 256 && self.0[count / 64] & 1 << (count % 64) != 0
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..256).filter(|count| self.contains(*count))
+    }
+}
+
+/// Which neighbour counts bring a dead cell to life and keep a live one alive,
+/// written `B3/S23` for Conway's rule. Larger-than-Life rules count over wider
+/// neighbourhoods, and sometimes count the cell itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    /// Counts giving birth.
+    birth: Counts,
+    /// Counts a live cell survives with.
+    survival: Counts,
+    neighborhood: Neighborhood,
+    /// Whether a live cell counts itself.
+    middle: bool,
+}
+
+/// Well known rules and their names.
+pub const NAMED_RULES: [(&str, &str); 5] = [
+    ("Conway", "B3/S23"),
+    ("HighLife", "B36/S23"),
+    ("Seeds", "B2/S"),
+    ("Day & Night", "B3678/S34678"),
+    ("Bosco", "R5,C2,M1,S34-58,B34-45,NM"),
+];
+
+impl Rule {
+    /// A rule over the eight cells around each cell.
+    pub fn new(birth: &[usize], survival: &[usize]) -> Result<Self, ParseRuleError> {
+        Self::with_neighborhood(Neighborhood::Moore(1), false, birth, survival)
+    }
+
+    /// A rule over `neighborhood`, where live cells count themselves when
+    /// `middle` is set.
+    pub fn with_neighborhood(
+        neighborhood: Neighborhood,
+        middle: bool,
+        birth: &[usize],
+        survival: &[usize],
+    ) -> Result<Self, ParseRuleError> {
+        if !(1..=MAX_RANGE).contains(&neighborhood.range()) {
+            return Err(ParseRuleError(format!(
+                "the range goes from 1 to {}, not {}",
+                MAX_RANGE,
+                neighborhood.range()
+            )));
+        }
+        let max_count = neighborhood.size() + middle as usize;
+        let set = |counts: &[usize]| {
+            let mut set = Counts::default();
+            for &count in counts {
+                if count > max_count {
+                    return Err(ParseRuleError(format!(
+                        "a cell counts at most {} neighbours, not {}",
+                        max_count, count
+                    )));
+                }
+                set.insert(count);
+            }
+            Ok(set)
+        };
+        Ok(Self {
+            birth: set(birth)?,
+            survival: set(survival)?,
+            neighborhood,
+            middle,
+        })
+    }
+
+    pub fn conway() -> Self {
+        Self::new(&[3], &[2, 3]).expect("Conway's counts fit the neighbourhood")
+    }
+
+    pub fn neighborhood(&self) -> Neighborhood {
+        self.neighborhood
+    }
+
+    /// Whether dead cells without live neighbours come to life, filling the
+    /// whole of an unbounded universe at once.
+    pub fn births_from_nothing(&self) -> bool {
+        self.birth.contains(0)
+    }
+
+    /// State of a cell in `state` with `neighbors` live neighbours at the next
+    /// generation.
+    pub fn next(&self, state: CellState, neighbors: usize) -> CellState {
+        let (counts, count) = match state {
+            CellState::Alive => (self.survival, neighbors + self.middle as usize),
+            CellState::Dead => (self.birth, neighbors),
+        };
+        if counts.contains(count) {
+            CellState::Alive
+        } else {
+            CellState::Dead
+        }
+    }
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self::conway()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRuleError(String);
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid rule: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseRuleError {}
+
+impl FromStr for Rule {
+    type Err = ParseRuleError;
+
+    /// Parses `B<counts>/S<counts>` in either order and any case, ending with
+    /// `V` for the von Neumann neighbourhood, or Larger-than-Life rules written
+    /// like `R5,C2,M1,S34-58,B34-45,NM`.
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let trimmed = rule.trim();
+        let parsed = if trimmed.starts_with(['R', 'r']) {
+            parse_larger_than_life(trimmed)
+        } else {
+            parse_life_like(trimmed)
+        };
+        parsed.map_err(|reason| ParseRuleError(format!("\"{}\", {}", rule, reason)))
+    }
+}
+
+fn parse_life_like(rule: &str) -> Result<Rule, String> {
+    let (rule, neighborhood) = match rule.strip_suffix(['V', 'v']) {
+        Some(rule) => (rule, Neighborhood::VonNeumann(1)),
+        None => (rule, Neighborhood::Moore(1)),
+    };
+    let max_count = neighborhood.size();
+
+    let mut birth = None;
+    let mut survival = None;
+    for part in rule.split('/') {
+        let mut chars = part.chars();
+        let counts = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('B') => &mut birth,
+            Some('S') => &mut survival,
+            _ => return Err("expected B<counts>/S<counts>".to_string()),
+        };
+        if counts.is_some() {
+            return Err("B or S is given twice".to_string());
+        }
+
+        let mut set = Counts::default();
+        for digit in chars {
+            match digit.to_digit(10) {
+                Some(count) if count as usize <= max_count => set.insert(count as usize),
+                _ => return Err(format!("'{}' isn't a count of neighbours", digit)),
+            }
+        }
+        *counts = Some(set);
+    }
+
+    match (birth, survival) {
+        (Some(birth), Some(survival)) => Ok(Rule {
+            birth,
+            survival,
+            neighborhood,
+            middle: false,
+        }),
+        _ => Err("expected B<counts>/S<counts>".to_string()),
+    }
+}
+
+/// Parses the range `R`, the number of states `C` (only 2, or 0 meaning 2),
+/// whether cells count themselves `M`, the counts `S` and `B` as lists of
+/// counts and intervals like `2,4-6` or `4..6`, and the neighbourhood `N`,
+/// Moore (`NM`, the default) or von Neumann (`NN`).
+fn parse_larger_than_life(rule: &str) -> Result<Rule, String> {
+    // Lists of counts carry on over the fields that don't start with a letter
+    let mut fields: Vec<(char, Vec<&str>)> = Vec::new();
+    for field in rule.split(',').map(str::trim) {
+        match field.chars().next() {
+            Some(key) if key.is_ascii_alphabetic() => {
+                fields.push((key.to_ascii_uppercase(), vec![&field[1..]]))
+            }
+            _ => match fields.last_mut() {
+                Some((_, values)) => values.push(field),
+                None => return Err("expected R<range> first".to_string()),
+            },
+        }
+    }
+
+    let number = |value: &str| {
+        value
+            .parse::<usize>()
+            .map_err(|_| format!("\"{}\" isn't a number", value))
+    };
+    let (mut range, mut middle, mut von_neumann) = (None, false, false);
+    let (mut birth, mut survival) = (None, None);
+    for (key, values) in &fields {
+        // Only the counts are lists
+        let value = || match values[..] {
+            [value] => Ok(value),
+            _ => Err(format!("{} takes a single value", key)),
+        };
+        match key {
+            'R' => range = Some(number(value()?)?),
+            'C' => {
+                if !matches!(number(value()?)?, 0 | 2) {
+                    return Err("only rules with two states are supported".to_string());
+                }
+            }
+            'M' => {
+                middle = match value()? {
+                    "0" => false,
+                    "1" => true,
+                    value => return Err(format!("expected M0 or M1, not M{}", value)),
+                }
+            }
+            'N' => {
+                von_neumann = match value()?.to_ascii_uppercase().as_str() {
+                    "M" => false,
+                    "N" => true,
+                    value => return Err(format!("unknown neighbourhood N{}", value)),
+                }
+            }
+            'B' | 'S' => {
+                let mut intervals = Vec::new();
+                for value in values.iter().filter(|value| !value.is_empty()) {
+                    let (low, high) = value
+                        .split_once("..")
+                        .or_else(|| value.split_once('-'))
+                        .unwrap_or((value, value));
+                    intervals.push((number(low)?, number(high)?));
+                }
+                let counts = if *key == 'B' {
+                    &mut birth
+                } else {
+                    &mut survival
+                };
+                if counts.replace(intervals).is_some() {
+                    return Err("B or S is given twice".to_string());
+                }
+            }
+            _ => return Err(format!("unknown field {}", key)),
+        }
+    }
+
+    let range = match range {
+        Some(range) if (1..=MAX_RANGE as usize).contains(&range) => range as u8,
+        Some(range) => {
+            return Err(format!(
+                "the range goes from 1 to {}, not {}",
+                MAX_RANGE, range
+            ))
+        }
+        None => return Err("expected R<range>".to_string()),
+    };
+    let neighborhood = if von_neumann {
+        Neighborhood::VonNeumann(range)
+    } else {
+        Neighborhood::Moore(range)
+    };
+    let max_count = neighborhood.size() + middle as usize;
+    let set = |intervals: Vec<(usize, usize)>| {
+        let mut set = Counts::default();
+        for (low, high) in intervals {
+            if high > max_count {
+                return Err(format!("a cell counts at most {} neighbours", max_count));
+            }
+            (low..=high).for_each(|count| set.insert(count));
+        }
+        Ok(set)
+    };
+
+    match (birth, survival) {
+        (Some(birth), Some(survival)) => Ok(Rule {
+            birth: set(birth)?,
+            survival: set(survival)?,
+            neighborhood,
+            middle,
+        }),
+        _ => Err("expected both B and S".to_string()),
+    }
+}
+
+impl fmt::Display for Rule {
+    /// Writes rules over the eight or four closest cells as `B3/S23`, with `V`
+    /// for the four, and the others as Larger-than-Life rules.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.neighborhood, self.middle) {
+            (Neighborhood::Moore(1) | Neighborhood::VonNeumann(1), false) => {
+                let digits = |counts: Counts| {
+                    counts
+                        .iter()
+                        .map(|count| count.to_string())
+                        .collect::<String>()
+                };
+                write!(f, "B{}/S{}", digits(self.birth), digits(self.survival))?;
+                if let Neighborhood::VonNeumann(_) = self.neighborhood {
+                    write!(f, "V")?;
+                }
+                Ok(())
+            }
+            (neighborhood, middle) => {
+                // Runs of consecutive counts as intervals
+                let list = |counts: Counts| {
+                    let mut runs: Vec<(usize, usize)> = Vec::new();
+                    for count in counts.iter() {
+                        match runs.last_mut() {
+                            Some((_, high)) if *high + 1 == count => *high = count,
+                            _ => runs.push((count, count)),
+                        }
+                    }
+                    runs.iter()
+                        .map(|(low, high)| {
+                            if low == high {
+                                low.to_string()
+                            } else {
+                                format!("{}-{}", low, high)
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",")
+                };
+                let shape = match neighborhood {
+                    Neighborhood::Moore(_) => 'M',
+                    Neighborhood::VonNeumann(_) => 'N',
+                };
+                write!(
+                    f,
+                    "R{},C2,M{},S{},B{},N{}",
+                    neighborhood.range(),
+                    middle as u8,
+                    list(self.survival),
+                    list(self.birth),
+                    shape
+                )
+            }
+        }
+    }
+}
+
+/// Steps `Cells` one generation at a time following a `Rule`, which can be
+/// swapped between generations, with the edges of the grid joined following a
+/// `Topology`.
+#[derive(Debug, Clone)]
+pub struct Life {
+    pub rule: Rule,
+    pub topology: Topology,
+    cells: Cells,
+    /// Where the next generation is built, to avoid allocating every step.
+    next: Cells,
+    generation: u64,
+}
+
+impl Life {
+    pub fn new(rule: Rule, cells: Cells) -> Self {
+        Self {
+            rule,
+            topology: Topology::default(),
+            next: cells.clone(),
+            cells,
+            generation: 0,
+        }
+    }
+
+    pub fn cells(&self) -> &Cells {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut Cells {
+        &mut self.cells
+    }
+
+    /// Generations stepped so far.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Moves every cell to the next generation at once.
+    pub fn step(&mut self) {
+        let offsets = self.rule.neighborhood.offsets().collect::<Vec<_>>();
+        for y in 0..self.cells.height {
+            for x in 0..self.cells.width {
+                let state = self.cells.get(x as isize, y as isize);
+                let neighbors = self.cells.neighbors(x, y, &offsets, self.topology);
+                let next = self.rule.next(state, neighbors);
+                self.next.set(x, y, next);
+            }
+        }
+
+        std::mem::swap(&mut self.cells, &mut self.next);
+        self.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cells from rows of `#` (alive) and `.` (dead), the first row at `y = 0`.
+    fn cells(rows: &[&str]) -> Cells {
+        let mut cells = Cells::new(rows[0].len(), rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == '#' {
+                    cells.set(x, y, CellState::Alive);
+                }
+            }
+        }
+        cells
+    }
+
+    /// Generations `rows` goes through under `rule`, the first being `rows`.
+    fn generations(rule: &str, rows: &[&str], count: usize) -> Vec<Cells> {
+        let mut life = Life::new(rule.parse().unwrap(), cells(rows));
+        (0..count)
+            .map(|_| {
+                let cells = life.cells().clone();
+                life.step();
+                cells
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rules_parse_and_print() {
+        for (_, rule) in NAMED_RULES {
+            assert_eq!(rule.parse::<Rule>().unwrap().to_string(), rule);
+        }
+        assert_eq!("b3/s23".parse(), Ok(Rule::conway()));
+        assert_eq!("S23/B3".parse(), Ok(Rule::conway()));
+        assert_eq!("B36/S23".parse(), Rule::new(&[3, 6], &[2, 3]));
+
+        for invalid in ["", "B3", "B3/S23/S1", "B9/S23", "B3/X23", "23/3", "B3/S2a"] {
+            assert!(invalid.parse::<Rule>().is_err(), "{}", invalid);
+        }
+
+        // Built by hand, the counts and the range are checked the same way
+        assert!(Rule::new(&[9], &[2, 3]).is_err());
+        assert!(Rule::with_neighborhood(Neighborhood::VonNeumann(1), true, &[5], &[]).is_ok());
+        assert!(Rule::with_neighborhood(Neighborhood::VonNeumann(1), false, &[5], &[]).is_err());
+        for range in [0, MAX_RANGE + 1] {
+            assert!(Rule::with_neighborhood(Neighborhood::Moore(range), false, &[1], &[]).is_err());
+        }
+    }
+
+    #[test]
+    fn still_lifes() {
+        let block = ["....", ".##.", ".##.", "...."];
+        let beehive = ["......", "..##..", ".#..#.", "..##..", "......"];
+        let loaf = ["......", "..##..", ".#..#.", "..#.#.", "...#..", "......"];
+
+        for rows in [&block[..], &beehive, &loaf] {
+            let generations = generations("B3/S23", rows, 3);
+            assert_eq!(generations[0], generations[1]);
+            assert_eq!(generations[1], generations[2]);
+        }
+
+        // Every cell of a block has 3 neighbours, which Day & Night keeps too
+        let generations = generations("B3678/S34678", &block, 2);
+        assert_eq!(generations[0], generations[1]);
+    }
+
+    #[test]
+    fn oscillators() {
+        let blinker = [".....", ".....", ".###.", ".....", "....."];
+        let toad = ["......", "......", "..###.", ".###..", "......", "......"];
+        let beacon = ["......", ".##...", ".##...", "...##.", "...##.", "......"];
+
+        for rows in [&blinker[..], &toad, &beacon] {
+            let generations = generations("B3/S23", rows, 3);
+            assert_ne!(generations[0], generations[1]);
+            assert_eq!(generations[0], generations[2]);
+        }
+
+        let generations = generations("B3/S23", &blinker, 2);
+        assert_eq!(
+            generations[1],
+            cells(&[".....", "..#..", "..#..", "..#..", "....."])
+        );
+    }
+
+    #[test]
+    fn gliders_move_diagonally() {
+        let glider = [".#....", "..#...", "###...", "......", "......", "......"];
+        let moved = ["......", "..#...", "...#..", ".###..", "......", "......"];
+
+        let generations = generations("B3/S23", &glider, 5);
+        assert_eq!(generations[4], cells(&moved));
+        assert_eq!(generations[4].population(), 5);
+    }
+
+    #[test]
+    fn other_rules() {
+        // Seeds: nothing survives, two neighbours give birth
+        let domino = ["....", ".##.", "....", "...."];
+        let seeds = generations("B2/S", &domino, 2);
+        assert_eq!(seeds[1], cells(&[".##.", "....", ".##.", "...."]));
+
+        // HighLife: six neighbours give birth, unlike in Conway's rule
+        let six = ["###", "...", "###"];
+        let born = |rule| generations(rule, &six, 2)[1].get(1, 1);
+        assert_eq!(born("B36/S23"), CellState::Alive);
+        assert_eq!(born("B3/S23"), CellState::Dead);
+    }
+
+    #[test]
+    fn rule_changes_between_generations() {
+        let mut life = Life::new(Rule::conway(), cells(&["....", ".##.", "....", "...."]));
+        life.step();
+        assert_eq!(life.cells().population(), 0);
+
+        *life.cells_mut() = cells(&["....", ".##.", "....", "...."]);
+        life.rule = "B2/S".parse().unwrap();
+        life.step();
+        assert_eq!(life.cells().population(), 4);
+        assert_eq!(life.generation(), 2);
+    }
+
+    #[test]
+    fn larger_neighborhoods() {
+        assert_eq!(Neighborhood::Moore(1).size(), 8);
+        assert_eq!(Neighborhood::VonNeumann(1).size(), 4);
+        assert_eq!(Neighborhood::Moore(5).size(), 120);
+        assert_eq!(Neighborhood::VonNeumann(2).size(), 12);
+
+        for rule in [
+            "B2/S013V",
+            "R2,C2,M0,S2-3,5,B3,NN",
+            "R5,C2,M1,S34-58,B34-45,NM",
+        ] {
+            assert_eq!(rule.parse::<Rule>().unwrap().to_string(), rule);
+        }
+        // Golly's notation for Larger-than-Life rules
+        let bosco = "R5,C0,M1,S34..58,B34..45,NM".parse::<Rule>().unwrap();
+        assert_eq!(bosco.to_string(), NAMED_RULES[4].1);
+        assert_eq!("r1,c0,m0,s2..3,b3,nm".parse(), Ok(Rule::conway()));
+        assert_eq!("R1,C2,S,B1-4,NN".parse(), "B1234/SV".parse::<Rule>());
+
+        for invalid in [
+            "B5/S23V",
+            "R8,C0,M0,S1,B1,NM",
+            "R1,C3,M0,S1,B1,NM",
+            "R1,C0,M0,S9,B1,NM",
+            "R1,C0,M2,S1,B1,NM",
+            "R1,C0,M0,S1,B1,NH",
+            "R1,C0,M0,S1,B1,NM,X3",
+            "R1,C0,M0,S1,B3,B2",
+            "R2,C0,S1",
+        ] {
+            assert!(invalid.parse::<Rule>().is_err(), "{}", invalid);
+        }
+
+        // A single cell brings its whole neighbourhood to life
+        let dot = [".....", ".....", "..#..", ".....", "....."];
+        let square = generations("R2,C2,M0,S,B1,NM", &dot, 2);
+        assert_eq!(
+            square[1],
+            cells(&["#####", "#####", "##.##", "#####", "#####"])
+        );
+        let diamond = generations("R2,C2,M0,S,B1,NN", &dot, 2);
+        assert_eq!(
+            diamond[1],
+            cells(&["..#..", ".###.", "##.##", ".###.", "..#.."])
+        );
+
+        // Counting itself, a lone cell survives with a count of 1
+        let middle = Rule::with_neighborhood(Neighborhood::Moore(1), true, &[1], &[1]).unwrap();
+        assert_eq!(middle.next(CellState::Alive, 0), CellState::Alive);
+        assert_eq!(middle.next(CellState::Dead, 0), CellState::Dead);
+        assert_eq!(middle.next(CellState::Dead, 1), CellState::Alive);
+        assert_eq!(middle.next(CellState::Alive, 1), CellState::Dead);
+    }
+
+    #[test]
+    fn edges_join_following_the_topology() {
+        let locate = |topology: Topology, x, y| topology.locate(x, y, 4, 3);
+        assert_eq!(locate(Topology::Bounded, 3, 2), Some((3, 2)));
+        assert_eq!(locate(Topology::Bounded, 4, 0), None);
+        assert_eq!(locate(Topology::Torus, 4, -1), Some((0, 2)));
+        assert_eq!(locate(Topology::KleinBottle, 4, 1), Some((0, 1)));
+        assert_eq!(locate(Topology::KleinBottle, 1, -1), Some((2, 2)));
+        assert_eq!(locate(Topology::KleinBottle, 1, 7), Some((1, 1)));
+        assert_eq!(locate(Topology::CrossSurface, 1, 3), Some((2, 0)));
+        assert_eq!(locate(Topology::CrossSurface, -1, 0), Some((3, 2)));
+
+        // A lightweight spaceship flying along y, two cells every four
+        // generations, so all the way round a grid of 16 in 32 of them
+        let ship = [".###", "#..#", "...#", "...#", "#.#."];
+        // Near the left edge or mirrored near the right one, transposed to fly
+        // along x
+        let grid = |mirrored: bool, transposed: bool| {
+            let mut cells = Cells::new(16, 16);
+            for (y, row) in ship.iter().enumerate() {
+                for (x, _) in row.chars().enumerate().filter(|(_, c)| *c == '#') {
+                    let (x, y) = (if mirrored { 12 - x } else { x + 3 }, y + 6);
+                    if transposed {
+                        cells.set(y, x, CellState::Alive);
+                    } else {
+                        cells.set(x, y, CellState::Alive);
+                    }
+                }
+            }
+            cells
+        };
+        let flown = |topology, cells| {
+            let mut life = Life::new(Rule::conway(), cells);
+            life.topology = topology;
+            for _ in 0..32 {
+                life.step();
+            }
+            life.cells().clone()
+        };
+
+        for transposed in [false, true] {
+            let ship = grid(false, transposed);
+            assert_eq!(flown(Topology::Torus, ship.clone()), ship);
+        }
+        // Across the top and bottom edges, left and right swap
+        assert_eq!(
+            flown(Topology::KleinBottle, grid(false, false)),
+            grid(true, false)
+        );
+        assert_eq!(
+            flown(Topology::KleinBottle, grid(false, true)),
+            grid(false, true)
+        );
+        assert_eq!(
+            flown(Topology::CrossSurface, grid(false, false)),
+            grid(true, false)
+        );
+        // and across the left and right ones of a cross-surface, top and bottom
+        assert_eq!(
+            flown(Topology::CrossSurface, grid(false, true)),
+            grid(true, true)
+        );
+        // Bounded edges wreck it
+        assert_ne!(
+            flown(Topology::Bounded, grid(false, false)),
+            grid(false, false)
+        );
+    }
+}
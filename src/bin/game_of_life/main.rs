@@ -1,201 +1,219 @@
 use bevy::prelude::*;
+use hashlife::HashLife;
+use life::{CellState, Cells, Life, Rule, Topology, NAMED_RULES};
+use pattern::Pattern;
+use universe::{Engine, SparseLife, Universe};
+
+mod editor;
+mod hashlife;
+mod life;
+mod pattern;
+mod universe;
+mod view;
 
 #[derive(Resource)]
 struct GameOfLifeConfig {
     alive_color: Color,
     dead_color: Color,
+    /// Past the edges of a bounded world.
+    edge_color: Color,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// A cell of the universe, `y` going upwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct GridCoordinate {
-    x: usize,
-    y: usize,
+    x: i64,
+    y: i64,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum CellState {
-    ALIVE,
-    DEAD,
+/// The automaton being shown, run by the engine picked with `--engine`.
+#[derive(Resource, Deref, DerefMut)]
+struct Game(Box<dyn Engine>);
+
+/// Keys switching to the rules of `NAMED_RULES`, in the same order.
+const RULE_KEYS: [KeyCode; 5] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+];
+
+/// Value following `name` on the command line.
+fn arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
 }
 
-#[derive(Component, Debug, Clone, Copy)]
-struct Cell {
-    coordinate: GridCoordinate,
-    state: CellState,
-}
-
-#[derive(Debug, Resource)]
-struct Cells {
-    cells: Vec<CellState>,
-    width: usize,
-    height: usize,
+/// Rule given with `--rule B36/S23` on the command line, Conway's otherwise.
+fn rule_from_args() -> Rule {
+    match arg_value("--rule").map(|rule| rule.parse()) {
+        Some(Ok(rule)) => rule,
+        Some(Err(err)) => panic!("{}", err),
+        None => Rule::conway(),
+    }
 }
 
-#[derive(Component, Debug)]
-struct CellNeighbors {
-    neighbors: Vec<GridCoordinate>,
+/// Edges of the world given with `--topology torus`, `None` for a world without
+/// edges.
+fn topology_from_args() -> Option<Topology> {
+    arg_value("--topology").map(|topology| match topology.as_str() {
+        "bounded" => Topology::Bounded,
+        "torus" => Topology::Torus,
+        "klein" => Topology::KleinBottle,
+        "cross" => Topology::CrossSurface,
+        _ => panic!(
+            "Unknown topology {}, expected bounded, torus, klein or cross",
+            topology
+        ),
+    })
 }
 
-fn create_cell(position: Vec2, size: Vec2, color: Color) -> SpriteBundle {
-    SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(size),
-            color,
-            ..Default::default()
-        },
-        transform: Transform::from_translation(Vec3::new(position.x, position.y, 0.0)),
-        ..Default::default()
+/// Width and height of a world with edges, given with `--world 120x80` or
+/// `--world 100` for a square.
+fn world_size() -> (usize, usize) {
+    let world = arg_value("--world").unwrap_or_else(|| "100".to_string());
+    let size = |size: &str| match size.parse() {
+        Ok(size) if size > 0 => size,
+        _ => panic!(
+            "Invalid --world {}, expected a size above 0 like 120x80",
+            world
+        ),
+    };
+    match world.split_once('x') {
+        Some((width, height)) => (size(width), size(height)),
+        None => (size(&world), size(&world)),
     }
 }
 
-fn setup_system(
-    mut commands: Commands,
-    mut windows: ResMut<Windows>,
-    _materials: ResMut<Assets<ColorMaterial>>,
-) {
-    let window = windows.get_primary_mut().unwrap();
-    window.set_title("Game Of Life".to_string());
-
-    commands.spawn(Camera2dBundle::default());
-
-    let alive_color = Color::GREEN;
-    let dead_color = Color::BLACK;
-
-    commands.insert_resource(GameOfLifeConfig {
-        alive_color,
-        dead_color,
-    });
-
-    let window_width = window.width();
-    let window_height = window.height();
+/// Engine given with `--engine hashlife` on the command line, the sparse one
+/// otherwise. A `--topology` runs on a grid of `--world` cells instead.
+fn engine_from_args(rule: Rule, universe: &Universe) -> Box<dyn Engine> {
+    let engine: Box<dyn Engine> = match (arg_value("--engine").as_deref(), topology_from_args()) {
+        (Some("hashlife"), None) => Box::new(HashLife::from_universe(rule, universe)),
+        (Some("sparse") | None, None) => Box::new(SparseLife::new(rule, universe.clone())),
+        (None, Some(topology)) => {
+            let (width, height) = world_size();
+            let mut life = Life::new(rule, Cells::new(width, height));
+            life.topology = topology;
+            life.set_universe(universe);
+            Box::new(life)
+        }
+        (Some(_), Some(_)) => panic!("--topology runs on a grid of its own, without --engine"),
+        (Some(engine), None) => panic!("Unknown engine {}, expected sparse or hashlife", engine),
+    };
+    assert!(
+        engine.supports(&rule),
+        "This engine can't run {}, B0 rules need a --topology",
+        rule
+    );
+    engine
+}
 
-    let width = 20;
-    let height = 20;
+/// Side of the random soup, given with `--size 60`.
+fn soup_size() -> i64 {
+    arg_value("--size").map_or(20, |size| size.parse().expect("Invalid --size"))
+}
 
-    let size = Vec2::new(window_width / width as f32, window_height / height as f32);
+/// Brings every cell of the `size` by `size` square around `center` to life or
+/// kills it, with even odds.
+fn randomize(game: &mut dyn Engine, center: GridCoordinate, size: i64) {
+    for y in 0..size {
+        for x in 0..size {
+            let state = if rand::random() {
+                CellState::Alive
+            } else {
+                CellState::Dead
+            };
+            game.set(center.x - size / 2 + x, center.y - size / 2 + y, state);
+        }
+    }
+}
 
-    let width = width + 2;
-    let height = height + 2;
+fn setup_system(mut commands: Commands) {
+    // Looking at the origin, where the soup is
+    commands.spawn(Camera2dBundle::default());
 
-    let mut cells = Vec::with_capacity(width * height);
-    cells.resize_with(width * height, || CellState::DEAD);
+    let config = GameOfLifeConfig {
+        alive_color: Color::GREEN,
+        dead_color: Color::BLACK,
+        edge_color: Color::DARK_GRAY,
+    };
 
-    for x in 1..width - 1 {
-        for y in 1..height - 1 {
-            let cell = Cell {
-                coordinate: GridCoordinate { x, y },
-                state: if rand::random() {
-                    CellState::ALIVE
-                } else {
-                    CellState::DEAD
-                },
-            };
+    let mut engine = engine_from_args(rule_from_args(), &Universe::new());
+    randomize(engine.as_mut(), GridCoordinate { x: 0, y: 0 }, soup_size());
 
-            cells[y * width + x] = cell.state;
-
-            let x = x as isize;
-            let y = y as isize;
-
-            let neighbors = vec![
-                (x - 1, y - 1),
-                (x, y - 1),
-                (x + 1, y - 1),
-                (x - 1, y),
-                // (x,     y),
-                (x + 1, y),
-                (x - 1, y + 1),
-                (x, y + 1),
-                (x + 1, y + 1),
-            ];
-
-            let neighbors = neighbors
-                .iter()
-                .map(|n| GridCoordinate {
-                    x: n.0 as usize,
-                    y: n.1 as usize,
-                })
-                .collect();
-
-            commands.spawn((
-                create_cell(
-                    Vec2::new(
-                        size.x * x as f32 - window_width / 2.0 - size.x / 2.0,
-                        size.y * y as f32 - window_height / 2.0 - size.y / 2.0,
-                    ),
-                    size,
-                    match cell.state {
-                        CellState::ALIVE => alive_color,
-                        CellState::DEAD => dead_color,
-                    },
-                ),
-                cell,
-                CellNeighbors { neighbors },
-            ));
+    // The cells of a world with edges stand out from what lies past them
+    match engine.bounds() {
+        Some(bounds) => {
+            commands.spawn(view::world_sprite(bounds, config.dead_color));
+            commands.insert_resource(ClearColor(config.edge_color));
         }
+        None => commands.insert_resource(ClearColor(config.dead_color)),
     }
-
-    commands.insert_resource(Cells {
-        cells,
-        width: width as usize,
-        height: height as usize,
-    });
+    commands.insert_resource(config);
+    commands.insert_resource(Game(engine));
 }
 
-fn count_neighbors(cells: &Cells, neighbors: &CellNeighbors) -> usize {
-    neighbors.neighbors.iter().fold(0, |mut state, coordinate| {
-        let x = coordinate.x;
-        let y = coordinate.y;
-        let cell_state = cells.cells[y * cells.width + x];
-        if cell_state == CellState::ALIVE {
-            state += 1;
-        };
-        state
-    })
+/// Switches to one of `NAMED_RULES` with the number keys, the cells carry on
+/// from where they are.
+fn rule_system(keys: Res<Input<KeyCode>>, mut game: ResMut<Game>) {
+    for (key, (name, rule)) in RULE_KEYS.iter().zip(NAMED_RULES) {
+        if keys.just_pressed(*key) {
+            let rule = rule.parse().unwrap();
+            if game.supports(&rule) {
+                game.set_rule(rule);
+            } else {
+                warn!("This engine can't run {}", name);
+            }
+        }
+    }
 }
 
+/// Steps the game whenever `UpdateTimer` finishes, never while it is paused.
 fn game_of_life_update_system(
     time: Res<Time>,
     mut update_timer: ResMut<UpdateTimer>,
-    config: Res<GameOfLifeConfig>,
-    mut cells: ResMut<Cells>,
-    query_cells: Query<(&Cell, &CellNeighbors)>,
-    mut query_materials: Query<(&Cell, &mut Sprite)>,
+    mut game: ResMut<Game>,
 ) {
     update_timer.0.tick(time.delta());
 
     if update_timer.0.just_finished() {
-        let mut new_materials = Vec::new();
-
-        for (cell, neighbors) in query_cells.iter() {
-            let count = count_neighbors(&cells, neighbors);
-
-            let x = cell.coordinate.x;
-            let y = cell.coordinate.y;
-
-            let width = cells.width;
-
-            let cell_state = cells.cells[y * cells.width + x];
-
-            let new_state = match (count, cell_state) {
-                (2, CellState::ALIVE) => CellState::ALIVE,
-                (3, _) => CellState::ALIVE,
-                _ => CellState::DEAD,
-            };
-
-            cells.cells[y * width + x] = new_state;
-
-            let material = match new_state {
-                CellState::ALIVE => config.alive_color,
-                CellState::DEAD => config.dead_color,
-            };
+        game.step();
+    }
+}
 
-            new_materials.push(material);
+/// Shows the rule, the generation, the population, the size of jumps and
+/// whether the game is paused.
+fn title_system(
+    game: Res<Game>,
+    update_timer: Res<UpdateTimer>,
+    jump_size: Res<editor::JumpSize>,
+    mut windows: ResMut<Windows>,
+) {
+    let name = NAMED_RULES
+        .iter()
+        .find(|(_, named)| named.parse() == Ok(game.rule()))
+        .map_or(String::new(), |(name, _)| format!("{} ", name));
+    let paused = if update_timer.0.paused() {
+        " (paused)"
+    } else {
+        ""
+    };
+    let title = format!(
+        "Game Of Life - {}{} - generation {} - population {} - jump 2^{}{}",
+        name,
+        game.rule(),
+        game.generation(),
+        game.population(),
+        jump_size.0.min(game.max_jump()),
+        paused
+    );
+
+    // The timer ticks every frame, so compare rather than rely on change detection
+    if let Some(window) = windows.get_primary_mut() {
+        if window.title() != title {
+            window.set_title(title);
         }
-
-        query_materials
-            .iter_mut()
-            .zip(new_materials)
-            .for_each(|((_, mut material), mat)| material.color = mat);
     }
 }
 
@@ -207,8 +225,30 @@ fn main() {
     App::new()
         .insert_resource(UpdateTimer(Timer::from_seconds(0.1, TimerMode::Repeating)))
         .add_plugins(DefaultPlugins)
+        .add_asset::<Pattern>()
+        .init_asset_loader::<pattern::RleLoader>()
+        .init_asset_loader::<pattern::PlaintextLoader>()
+        .init_resource::<editor::JumpSize>()
+        .init_resource::<view::ChunkSprites>()
         .add_startup_system(setup_system)
-        .add_system(game_of_life_update_system)
-        // .add_system(mouse_events_system.system())
+        .add_startup_system(pattern::load_pattern_system)
+        .add_system(view::camera_system)
+        .add_system(rule_system)
+        .add_system(editor::paint_system)
+        .add_system(editor::controls_system)
+        .add_system(pattern::place_pattern_system)
+        .add_system(pattern::stamp_pattern_system)
+        .add_system(pattern::save_pattern_system)
+        .add_system(
+            game_of_life_update_system
+                .after(rule_system)
+                .after(view::camera_system)
+                .after(editor::paint_system)
+                .after(editor::controls_system)
+                .after(pattern::place_pattern_system)
+                .after(pattern::stamp_pattern_system),
+        )
+        .add_system(view::chunk_sprites_system.after(game_of_life_update_system))
+        .add_system(title_system.after(game_of_life_update_system))
         .run();
 }
@@ -0,0 +1,649 @@
+//! Patterns in the usual Life file formats, RLE (`.rle`) and Plaintext
+//! (`.cells`), loaded as assets and written back out from the universe.
+//!
+//! `--pattern <path>` on the command line loads a pattern from the assets and
+//! places it at the origin, P stamps it again under the cursor and S saves the
+//! universe to the path given with `--save`.
+
+use super::*;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use std::fmt::{self, Write};
+
+/// Longest line of the RLE written out, as the format recommends.
+const RLE_LINE_LENGTH: usize = 70;
+
+/// Most cells an RLE header can ask for, the pattern being stored whole.
+const MAX_RLE_AREA: usize = 1 << 24;
+
+/// A pattern as found in a file, its first row at the top.
+#[derive(Debug, Clone, PartialEq, Eq, TypeUuid)]
+#[uuid = "3c1f9a6e-52d8-4b7e-8e0a-f4d2b6c9a185"]
+pub struct Pattern {
+    pub name: Option<String>,
+    /// Rule the pattern is meant for, when the file gives one.
+    pub rule: Option<Rule>,
+    /// The first row at `y = 0`, unlike in the universe.
+    pub cells: Cells,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePatternError(String);
+
+impl fmt::Display for ParsePatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pattern: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParsePatternError {}
+
+/// `B3/S23`, or the older `23/3` giving the survival counts first.
+fn parse_rule(rule: &str) -> Result<Rule, ParsePatternError> {
+    let rule = match rule.split_once('/') {
+        Some((survival, birth))
+            if survival
+                .chars()
+                .chain(birth.chars())
+                .all(|c| c.is_ascii_digit()) =>
+        {
+            format!("B{}/S{}", birth, survival)
+        }
+        _ => rule.to_string(),
+    };
+    rule.parse()
+        .map_err(|err: life::ParseRuleError| ParsePatternError(err.to_string()))
+}
+
+impl Pattern {
+    /// Parses RLE: `#` comments, a `x = 3, y = 3, rule = B3/S23` header and runs
+    /// of dead (`b`) and live (`o`) cells, rows ending with `$` and the pattern
+    /// with `!`.
+    pub fn from_rle(rle: &str) -> Result<Self, ParsePatternError> {
+        let error = |reason: String| ParsePatternError(reason);
+
+        let mut name = None;
+        let mut header = None;
+        let mut body = String::new();
+        for line in rle.lines().map(str::trim) {
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some(pattern_name) = comment.strip_prefix('N') {
+                    name = Some(pattern_name.trim().to_string());
+                }
+            } else if header.is_none() {
+                if !line.is_empty() {
+                    header = Some(line);
+                }
+            } else {
+                body.push_str(line);
+            }
+        }
+
+        let header = header.ok_or_else(|| error("no \"x = .., y = ..\" header".to_string()))?;
+        // The rule comes last, Larger-than-Life ones have commas of their own
+        let (header, rule) = match header.split_once("rule") {
+            Some((sizes, rule)) => {
+                let rule = rule
+                    .trim_start()
+                    .strip_prefix('=')
+                    .ok_or_else(|| error(format!("\"rule{}\" isn't a header field", rule)))?;
+                (
+                    sizes.trim_end().trim_end_matches(','),
+                    Some(parse_rule(rule.trim())?),
+                )
+            }
+            None => (header, None),
+        };
+        let (mut width, mut height) = (None, None);
+        for field in header.split(',') {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| error(format!("\"{}\" isn't a header field", field.trim())))?;
+            let size = || {
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| error(format!("\"{}\" isn't a size", value.trim())))
+            };
+            match key.trim() {
+                "x" => width = Some(size()?),
+                "y" => height = Some(size()?),
+                _ => {}
+            }
+        }
+        let (width, height) = match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err(error("the header misses x or y".to_string())),
+        };
+        if width
+            .checked_mul(height)
+            .filter(|&area| area <= MAX_RLE_AREA)
+            .is_none()
+        {
+            return Err(error(format!(
+                "a {}x{} pattern is larger than the {} cells allowed",
+                width, height, MAX_RLE_AREA
+            )));
+        }
+
+        let mut cells = Cells::new(width, height);
+        let (mut x, mut y): (usize, usize) = (0, 0);
+        let mut count: Option<usize> = None;
+        for c in body.chars() {
+            if let Some(digit) = c.to_digit(10) {
+                count = Some(
+                    count
+                        .unwrap_or(0)
+                        .saturating_mul(10)
+                        .saturating_add(digit as usize),
+                );
+                continue;
+            }
+
+            let run = count.take().unwrap_or(1);
+            match c {
+                '!' => break,
+                '$' => {
+                    x = 0;
+                    y = y.saturating_add(run);
+                }
+                'b' | '.' => x = x.saturating_add(run),
+                // States of multi-state rules are all alive here
+                c if c.is_ascii_alphabetic() => {
+                    if x.saturating_add(run) > width || y >= height {
+                        return Err(error(format!(
+                            "cells at ({}, {}) are outside the {}x{} pattern",
+                            x, y, width, height
+                        )));
+                    }
+                    for _ in 0..run {
+                        cells.set(x, y, CellState::Alive);
+                        x += 1;
+                    }
+                }
+                _ => return Err(error(format!("unexpected '{}'", c))),
+            }
+        }
+
+        Ok(Self { name, rule, cells })
+    }
+
+    /// Parses Plaintext: `!` comments then one line per row, `.` for dead cells
+    /// and `O` for live ones.
+    pub fn from_plaintext(text: &str) -> Result<Self, ParsePatternError> {
+        let mut name = None;
+        let mut rows = Vec::new();
+        for line in text.lines().map(str::trim_end) {
+            if let Some(comment) = line.strip_prefix('!') {
+                if let Some(pattern_name) = comment.strip_prefix("Name:") {
+                    name = Some(pattern_name.trim().to_string());
+                }
+            } else {
+                rows.push(line);
+            }
+        }
+
+        let width = rows
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut cells = Cells::new(width, rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                match c {
+                    'O' | '*' => cells.set(x, y, CellState::Alive),
+                    '.' => {}
+                    _ => return Err(ParsePatternError(format!("unexpected '{}'", c))),
+                }
+            }
+        }
+
+        Ok(Self {
+            name,
+            rule: None,
+            cells,
+        })
+    }
+
+    /// The live cells of `universe`, cropped to the smallest rectangle holding
+    /// them, or `None` when that rectangle is larger than a pattern read from
+    /// a file may be.
+    pub fn capture(universe: &Universe, rule: Option<Rule>) -> Option<Self> {
+        let mut cells = Cells::new(0, 0);
+        if let Some(((min_x, min_y), (max_x, max_y))) = universe.bounds() {
+            let span = |min: i64, max: i64| usize::try_from(max as i128 - min as i128 + 1).ok();
+            let (width, height) = (span(min_x, max_x)?, span(min_y, max_y)?);
+            width
+                .checked_mul(height)
+                .filter(|&area| area <= MAX_RLE_AREA)?;
+
+            cells = Cells::new(width, height);
+            for (x, y) in universe.alive() {
+                // The universe's rows go upwards
+                cells.set((x - min_x) as usize, (max_y - y) as usize, CellState::Alive);
+            }
+        }
+
+        Some(Self {
+            name: None,
+            rule,
+            cells,
+        })
+    }
+
+    /// Copies the whole pattern, dead cells included, into `universe` with its
+    /// top left corner at `corner`.
+    pub fn place(&self, universe: &mut Universe, corner: GridCoordinate) {
+        for py in 0..self.cells.height() {
+            for px in 0..self.cells.width() {
+                universe.set(
+                    corner.x + px as i64,
+                    corner.y - py as i64,
+                    self.cells.get(px as isize, py as isize),
+                );
+            }
+        }
+    }
+
+    /// Top left corner placing the pattern around `center`.
+    pub fn corner_around(&self, center: GridCoordinate) -> GridCoordinate {
+        GridCoordinate {
+            x: center.x - self.cells.width() as i64 / 2,
+            y: center.y + self.cells.height() as i64 / 2,
+        }
+    }
+
+    fn row(&self, y: usize) -> impl Iterator<Item = CellState> + '_ {
+        (0..self.cells.width()).map(move |x| self.cells.get(x as isize, y as isize))
+    }
+
+    pub fn to_rle(&self) -> String {
+        // Runs of cells, leaving out the dead ones ending a row and the empty
+        // rows ending the pattern
+        let mut tokens = Vec::new();
+        let mut rows_ended = 0;
+        for y in 0..self.cells.height() {
+            if y > 0 {
+                rows_ended += 1;
+            }
+            let row = self.row(y).collect::<Vec<_>>();
+            let end = match row.iter().rposition(|state| *state == CellState::Alive) {
+                Some(last) => last + 1,
+                None => continue,
+            };
+
+            if rows_ended > 0 {
+                tokens.push(run(rows_ended, '$'));
+                rows_ended = 0;
+            }
+            let mut x = 0;
+            while x < end {
+                let length = row[x..end]
+                    .iter()
+                    .take_while(|state| **state == row[x])
+                    .count();
+                let tag = match row[x] {
+                    CellState::Alive => 'o',
+                    CellState::Dead => 'b',
+                };
+                tokens.push(run(length, tag));
+                x += length;
+            }
+        }
+        tokens.push("!".to_string());
+
+        let mut rle = String::new();
+        if let Some(name) = &self.name {
+            writeln!(rle, "#N {}", name).unwrap();
+        }
+        write!(
+            rle,
+            "x = {}, y = {}",
+            self.cells.width(),
+            self.cells.height()
+        )
+        .unwrap();
+        if let Some(rule) = &self.rule {
+            write!(rle, ", rule = {}", rule).unwrap();
+        }
+        rle.push('\n');
+
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length + token.len() > RLE_LINE_LENGTH {
+                rle.push('\n');
+                line_length = 0;
+            }
+            line_length += token.len();
+            rle.push_str(&token);
+        }
+        rle.push('\n');
+        rle
+    }
+
+    /// Plaintext has no room for the rule, it is left out.
+    pub fn to_plaintext(&self) -> String {
+        let mut text = String::new();
+        if let Some(name) = &self.name {
+            writeln!(text, "!Name: {}", name).unwrap();
+        }
+        for y in 0..self.cells.height() {
+            for state in self.row(y) {
+                text.push(match state {
+                    CellState::Alive => 'O',
+                    CellState::Dead => '.',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// `length` times `tag`, written as RLE.
+fn run(length: usize, tag: char) -> String {
+    if length == 1 {
+        tag.to_string()
+    } else {
+        format!("{}{}", length, tag)
+    }
+}
+
+#[derive(Default)]
+pub struct RleLoader;
+
+impl AssetLoader for RleLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let pattern = Pattern::from_rle(std::str::from_utf8(bytes)?)?;
+            load_context.set_default_asset(LoadedAsset::new(pattern));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["rle"]
+    }
+}
+
+#[derive(Default)]
+pub struct PlaintextLoader;
+
+impl AssetLoader for PlaintextLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let pattern = Pattern::from_plaintext(std::str::from_utf8(bytes)?)?;
+            load_context.set_default_asset(LoadedAsset::new(pattern));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["cells"]
+    }
+}
+
+/// The pattern given with `--pattern`.
+#[derive(Resource)]
+pub struct PatternHandle(pub Handle<Pattern>);
+
+pub fn load_pattern_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    if let Some(path) = arg_value("--pattern") {
+        commands.insert_resource(PatternHandle(asset_server.load(path)));
+    }
+}
+
+/// Replaces the universe with the pattern once it is loaded, around the
+/// origin, and switches to its rule if it has one.
+pub fn place_pattern_system(
+    mut events: EventReader<AssetEvent<Pattern>>,
+    handle: Option<Res<PatternHandle>>,
+    patterns: Res<Assets<Pattern>>,
+    mut game: ResMut<Game>,
+) {
+    let handle = match handle {
+        Some(handle) => handle,
+        None => return,
+    };
+
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: created } if *created == handle.0 => {
+                let pattern = match patterns.get(created) {
+                    Some(pattern) => pattern,
+                    None => continue,
+                };
+
+                let mut universe = Universe::new();
+                let corner = pattern.corner_around(GridCoordinate { x: 0, y: 0 });
+                pattern.place(&mut universe, corner);
+                game.set_universe(&universe);
+                match pattern.rule {
+                    Some(rule) if !game.supports(&rule) => {
+                        warn!("This engine can't run {}, the rule stays", rule)
+                    }
+                    Some(rule) => game.set_rule(rule),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// P stamps the pattern given with `--pattern` under the cursor.
+pub fn stamp_pattern_system(
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    handle: Option<Res<PatternHandle>>,
+    patterns: Res<Assets<Pattern>>,
+    mut game: ResMut<Game>,
+) {
+    if !keys.just_pressed(KeyCode::P) {
+        return;
+    }
+
+    let pattern = match handle.and_then(|handle| patterns.get(&handle.0)) {
+        Some(pattern) => pattern,
+        None => return,
+    };
+    let (camera, projection) = match cameras.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    if let Some(center) = view::cursor_cell(&windows, camera, projection) {
+        let mut universe = game.universe();
+        pattern.place(&mut universe, pattern.corner_around(center));
+        game.set_universe(&universe);
+    }
+}
+
+/// S saves the live cells of the universe to the path given with `--save`, as
+/// Plaintext when it ends with `.cells` and as RLE otherwise.
+pub fn save_pattern_system(keys: Res<Input<KeyCode>>, game: Res<Game>) {
+    if !keys.just_pressed(KeyCode::S) {
+        return;
+    }
+
+    let path = arg_value("--save").unwrap_or_else(|| "pattern.rle".to_string());
+    let pattern = match Pattern::capture(&game.universe(), Some(game.rule())) {
+        Some(pattern) => pattern,
+        None => {
+            error!(
+                "The universe spans more than the {} cells a pattern may hold, not saving it",
+                MAX_RLE_AREA
+            );
+            return;
+        }
+    };
+    let text = if path.ends_with(".cells") {
+        pattern.to_plaintext()
+    } else {
+        pattern.to_rle()
+    };
+
+    match std::fs::write(&path, text) {
+        Ok(()) => info!("Saved the universe to {}", path),
+        Err(err) => error!("Failed to save the universe to {}: {}", path, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plaintext(rows: &[&str]) -> Cells {
+        Pattern::from_plaintext(&rows.join("\n")).unwrap().cells
+    }
+
+    #[test]
+    fn shipped_patterns_parse() {
+        let glider =
+            Pattern::from_rle(include_str!("../../../assets/game_of_life/glider.rle")).unwrap();
+        assert_eq!(glider.name.as_deref(), Some("Glider"));
+        assert_eq!(glider.rule, Some(Rule::conway()));
+        assert_eq!(glider.cells, plaintext(&[".O.", "..O", "OOO"]));
+
+        let gun = Pattern::from_rle(include_str!(
+            "../../../assets/game_of_life/gosper_glider_gun.rle"
+        ))
+        .unwrap();
+        assert_eq!((gun.cells.width(), gun.cells.height()), (36, 9));
+        assert_eq!(gun.cells.population(), 36);
+
+        let lwss = Pattern::from_plaintext(include_str!(
+            "../../../assets/game_of_life/lightweight_spaceship.cells"
+        ))
+        .unwrap();
+        assert_eq!(lwss.name.as_deref(), Some("LWSS"));
+        assert_eq!(lwss.cells, plaintext(&[".O..O", "O....", "O...O", "OOOO."]));
+    }
+
+    #[test]
+    fn rle_details() {
+        let pattern = Pattern::from_rle("x = 5, y = 4, rule = 23/36\n2o$\n\n2$3b2o!").unwrap();
+        assert_eq!(pattern.rule, Some("B36/S23".parse().unwrap()));
+        assert_eq!(
+            pattern.cells,
+            plaintext(&["OO...", ".....", ".....", "...OO"])
+        );
+
+        // Larger-than-Life rules, here Bosco's, have commas of their own
+        let bosco = Pattern::from_rle("x = 1, y = 1, rule = R5,C0,M1,S34..58,B34..45,NM\no!");
+        assert_eq!(
+            bosco.unwrap().rule,
+            Some("R5,C2,M1,S34-58,B34-45,NM".parse().unwrap())
+        );
+
+        for invalid in [
+            "",
+            "bo$2bo$3o!",
+            "x = 3\nbo!",
+            "x = 3, y = 3, rule = B9/S23\nbo!",
+            "x = 2, y = 1\n3o!",
+            "x = 2, y = 1\n$o!",
+            "x = 2, y = 1\no?!",
+            // Sizes and runs too large to allocate or count
+            "x = 100000, y = 100000\no!",
+            "x = 18446744073709551615, y = 2\no!",
+            "x = 2, y = 1\n99999999999999999999999o!",
+        ] {
+            assert!(Pattern::from_rle(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn written_patterns_read_back() {
+        let gun = Pattern::from_rle(include_str!(
+            "../../../assets/game_of_life/gosper_glider_gun.rle"
+        ))
+        .unwrap();
+        let rle = gun.to_rle();
+        assert!(rle.lines().all(|line| line.len() <= RLE_LINE_LENGTH));
+        assert_eq!(Pattern::from_rle(&rle).unwrap(), gun);
+
+        let plaintext = gun.to_plaintext();
+        let read = Pattern::from_plaintext(&plaintext).unwrap();
+        assert_eq!((read.name, read.cells), (gun.name, gun.cells));
+
+        let gap = Pattern {
+            name: None,
+            rule: None,
+            cells: self::plaintext(&["O..", "...", "...", "..O", "..."]),
+        };
+        assert_eq!(gap.to_rle(), "x = 3, y = 5\no3$2bo!\n");
+        assert_eq!(Pattern::from_rle(&gap.to_rle()).unwrap(), gap);
+    }
+
+    #[test]
+    fn placed_upright_and_captured_back() {
+        let glider = Pattern::from_plaintext(".O.\n..O\nOOO").unwrap();
+        let mut universe = Universe::new();
+        glider.place(&mut universe, GridCoordinate { x: -1, y: 1 });
+
+        // The top row is the highest in the universe
+        assert_eq!(universe.get(0, 1), CellState::Alive);
+        assert_eq!(universe.get(1, 0), CellState::Alive);
+        assert_eq!(universe.get(-1, -1), CellState::Alive);
+        assert_eq!(universe.population(), 5);
+        assert_eq!(Pattern::capture(&universe, None), Some(glider.clone()));
+
+        let corner = glider.corner_around(GridCoordinate { x: 0, y: 0 });
+        assert_eq!(corner, GridCoordinate { x: -1, y: 1 });
+
+        // Dead cells of the pattern are placed too
+        let block = Pattern::from_plaintext("OO.\nOO.").unwrap();
+        block.place(&mut universe, GridCoordinate { x: -1, y: 1 });
+        assert_eq!(universe.get(-1, 1), CellState::Alive);
+        assert_eq!(universe.get(1, 0), CellState::Dead);
+
+        assert_eq!(
+            Pattern::capture(&Universe::new(), None)
+                .unwrap()
+                .cells
+                .population(),
+            0
+        );
+    }
+
+    #[test]
+    fn sprawling_universes_are_not_captured() {
+        let mut universe = Universe::new();
+        universe.set(0, 0, CellState::Alive);
+        universe.set(1 << 20, 1 << 20, CellState::Alive);
+        assert_eq!(Pattern::capture(&universe, None), None);
+
+        universe.set(i64::MIN, i64::MAX, CellState::Alive);
+        assert_eq!(Pattern::capture(&universe, None), None);
+    }
+
+    #[test]
+    fn gliders_leave_the_gun() {
+        let gun = Pattern::from_rle(include_str!(
+            "../../../assets/game_of_life/gosper_glider_gun.rle"
+        ))
+        .unwrap();
+        let mut universe = Universe::new();
+        gun.place(&mut universe, GridCoordinate { x: 0, y: 0 });
+
+        let mut life = SparseLife::new(gun.rule.unwrap(), universe);
+        for _ in 0..300 {
+            life.step();
+        }
+        // Back to its first shape, having fired a glider every 30 generations
+        assert_eq!(life.universe().population(), 36 + 10 * 5);
+    }
+}
@@ -0,0 +1,510 @@
+//! An unbounded universe for Life-like rules, independent of Bevy. Live cells
+//! are kept in square chunks, created as patterns spread into them and dropped
+//! once they die out, so the universe grows with what lives in it.
+
+use super::life::{CellState, Cells, Life, Rule};
+use std::collections::{HashMap, HashSet};
+
+/// Cells on each side of a chunk.
+pub const CHUNK_SIZE: usize = 16;
+
+/// Lowest and highest corners of a rectangle of chunks.
+pub type ChunkRange = ((i64, i64), (i64, i64));
+
+/// Chunk holding the cell `(x, y)`, and where in the chunk the cell is.
+pub fn locate(x: i64, y: i64) -> ((i64, i64), (usize, usize)) {
+    let size = CHUNK_SIZE as i64;
+    (
+        (x.div_euclid(size), y.div_euclid(size)),
+        (x.rem_euclid(size) as usize, y.rem_euclid(size) as usize),
+    )
+}
+
+/// Cells everywhere, dead unless set otherwise. Only chunks with live cells are
+/// stored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Universe {
+    chunks: HashMap<(i64, i64), Cells>,
+}
+
+impl Universe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, x: i64, y: i64) -> CellState {
+        let (chunk, (x, y)) = locate(x, y);
+        self.chunks
+            .get(&chunk)
+            .map_or(CellState::Dead, |cells| cells.get(x as isize, y as isize))
+    }
+
+    pub fn set(&mut self, x: i64, y: i64, state: CellState) {
+        let (chunk, (x, y)) = locate(x, y);
+        match state {
+            CellState::Alive => self
+                .chunks
+                .entry(chunk)
+                .or_insert_with(|| Cells::new(CHUNK_SIZE, CHUNK_SIZE))
+                .set(x, y, state),
+            CellState::Dead => {
+                if let Some(cells) = self.chunks.get_mut(&chunk) {
+                    cells.set(x, y, state);
+                    if cells.population() == 0 {
+                        self.chunks.remove(&chunk);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Chunks holding live cells, in no particular order.
+    #[cfg(test)]
+    pub fn chunks(&self) -> impl Iterator<Item = (i64, i64)> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    /// Chunks with live cells in `range`, and their cells.
+    pub fn chunks_in(
+        &self,
+        ((min_x, min_y), (max_x, max_y)): ChunkRange,
+    ) -> Vec<((i64, i64), Cells)> {
+        self.chunks
+            .iter()
+            .filter(|((x, y), _)| (min_x..=max_x).contains(x) && (min_y..=max_y).contains(y))
+            .map(|(chunk, cells)| (*chunk, cells.clone()))
+            .collect()
+    }
+
+    /// Coordinates of the live cells, in no particular order.
+    pub fn alive(&self) -> impl Iterator<Item = (i64, i64)> + '_ {
+        let size = CHUNK_SIZE as i64;
+        self.chunks.iter().flat_map(move |((cx, cy), cells)| {
+            cells
+                .alive()
+                .map(move |(x, y)| (cx * size + x as i64, cy * size + y as i64))
+        })
+    }
+
+    pub fn population(&self) -> usize {
+        self.chunks.values().map(Cells::population).sum()
+    }
+
+    /// Lowest and highest corners of the smallest rectangle holding the live
+    /// cells, `None` when there are none.
+    pub fn bounds(&self) -> Option<((i64, i64), (i64, i64))> {
+        self.alive().fold(None, |bounds, (x, y)| match bounds {
+            None => Some(((x, y), (x, y))),
+            Some(((min_x, min_y), (max_x, max_y))) => {
+                Some(((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y))))
+            }
+        })
+    }
+
+    #[cfg(test)]
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// The universe one generation later under `rule`.
+    pub fn next(&self, rule: &Rule) -> Self {
+        // Cells can only come to life next to live ones, so in the chunks
+        // holding some or around them
+        let candidates = self
+            .chunks
+            .keys()
+            .flat_map(|(cx, cy)| {
+                (-1..=1).flat_map(move |dy| (-1..=1).map(move |dx| (cx + dx, cy + dy)))
+            })
+            .collect::<HashSet<_>>();
+
+        // Neighbourhoods reach at most `MAX_RANGE` cells away, so into the
+        // chunks around at most
+        let offsets = rule.neighborhood().offsets().collect::<Vec<_>>();
+        let mut chunks = HashMap::new();
+        for (cx, cy) in candidates {
+            // The chunk and the eight around it, most of them usually empty
+            let mut around = [[None; 3]; 3];
+            for (dy, row) in around.iter_mut().enumerate() {
+                for (dx, chunk) in row.iter_mut().enumerate() {
+                    *chunk = self.chunks.get(&(cx + dx as i64 - 1, cy + dy as i64 - 1));
+                }
+            }
+            let size = CHUNK_SIZE as isize;
+            let get = |x: isize, y: isize| {
+                let (dx, dy) = (x.div_euclid(size), y.div_euclid(size));
+                around[(dy + 1) as usize][(dx + 1) as usize]
+                    .map_or(CellState::Dead, |cells: &Cells| {
+                        cells.get(x.rem_euclid(size), y.rem_euclid(size))
+                    })
+            };
+
+            let mut next = Cells::new(CHUNK_SIZE, CHUNK_SIZE);
+            let mut alive = false;
+            for y in 0..size {
+                for x in 0..size {
+                    let neighbors = offsets
+                        .iter()
+                        .filter(|(dx, dy)| get(x + dx, y + dy) == CellState::Alive)
+                        .count();
+                    if rule.next(get(x, y), neighbors) == CellState::Alive {
+                        next.set(x as usize, y as usize, CellState::Alive);
+                        alive = true;
+                    }
+                }
+            }
+            if alive {
+                chunks.insert((cx, cy), next);
+            }
+        }
+
+        Self { chunks }
+    }
+}
+
+/// What the app needs of an engine running a Life-like rule in an unbounded
+/// universe: cells to show and edit, and generations to step through.
+pub trait Engine: Send + Sync {
+    fn rule(&self) -> Rule;
+    fn set_rule(&mut self, rule: Rule);
+    /// Generations stepped so far.
+    fn generation(&self) -> u64;
+    fn population(&self) -> u64;
+
+    fn get(&self, x: i64, y: i64) -> CellState;
+    fn set(&mut self, x: i64, y: i64, state: CellState);
+    /// All the live cells at once.
+    fn universe(&self) -> Universe;
+    fn set_universe(&mut self, universe: &Universe);
+    /// Chunks with live cells in `range`, and their cells.
+    fn chunks_in(&self, range: ChunkRange) -> Vec<((i64, i64), Cells)>;
+
+    /// Whether the engine can run `rule`, all of them unless told otherwise.
+    fn supports(&self, _rule: &Rule) -> bool {
+        true
+    }
+
+    /// Lowest and highest corners of the world, `None` when it has no edges.
+    fn bounds(&self) -> Option<((i64, i64), (i64, i64))> {
+        None
+    }
+
+    fn step(&mut self);
+
+    /// Largest `k` worth giving `step_pow2`, small unless the engine jumps
+    /// faster than one generation at a time.
+    fn max_jump(&self) -> u32 {
+        10
+    }
+
+    /// Steps `2^k` generations, one at a time unless the engine knows better.
+    fn step_pow2(&mut self, k: u32) {
+        for _ in 0..1u64 << k {
+            self.step();
+        }
+    }
+}
+
+/// Steps a `Universe` one generation at a time following a `Rule`, like `Life`
+/// does for a bounded grid.
+#[derive(Debug, Clone)]
+pub struct SparseLife {
+    pub rule: Rule,
+    universe: Universe,
+    generation: u64,
+}
+
+impl SparseLife {
+    pub fn new(rule: Rule, universe: Universe) -> Self {
+        Self {
+            rule,
+            universe,
+            generation: 0,
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.universe = self.universe.next(&self.rule);
+        self.generation += 1;
+    }
+}
+
+impl Engine for SparseLife {
+    fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    fn set_rule(&mut self, rule: Rule) {
+        self.rule = rule;
+    }
+
+    /// Rules that leave empty space empty, the universe only grows around
+    /// live cells.
+    fn supports(&self, rule: &Rule) -> bool {
+        !rule.births_from_nothing()
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn population(&self) -> u64 {
+        self.universe.population() as u64
+    }
+
+    fn get(&self, x: i64, y: i64) -> CellState {
+        self.universe.get(x, y)
+    }
+
+    fn set(&mut self, x: i64, y: i64, state: CellState) {
+        self.universe.set(x, y, state);
+    }
+
+    fn universe(&self) -> Universe {
+        self.universe.clone()
+    }
+
+    fn set_universe(&mut self, universe: &Universe) {
+        self.universe = universe.clone();
+    }
+
+    fn chunks_in(&self, range: ChunkRange) -> Vec<((i64, i64), Cells)> {
+        self.universe.chunks_in(range)
+    }
+
+    fn step(&mut self) {
+        SparseLife::step(self);
+    }
+}
+
+/// Cell of the grid at `(x, y)`, the grid being centred on the origin.
+fn grid_cell(cells: &Cells, x: i64, y: i64) -> Option<(usize, usize)> {
+    let (x, y) = (x + cells.width() as i64 / 2, y + cells.height() as i64 / 2);
+    ((0..cells.width() as i64).contains(&x) && (0..cells.height() as i64).contains(&y))
+        .then_some((x as usize, y as usize))
+}
+
+/// The bounded grid, centred on the origin, its edges joined following its
+/// topology. Cells can't be brought to life past them.
+impl Engine for Life {
+    fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    fn set_rule(&mut self, rule: Rule) {
+        self.rule = rule;
+    }
+
+    fn generation(&self) -> u64 {
+        Life::generation(self)
+    }
+
+    fn population(&self) -> u64 {
+        self.cells().population() as u64
+    }
+
+    fn get(&self, x: i64, y: i64) -> CellState {
+        grid_cell(self.cells(), x, y).map_or(CellState::Dead, |(x, y)| {
+            self.cells().get(x as isize, y as isize)
+        })
+    }
+
+    fn set(&mut self, x: i64, y: i64, state: CellState) {
+        if let Some((x, y)) = grid_cell(self.cells(), x, y) {
+            self.cells_mut().set(x, y, state);
+        }
+    }
+
+    fn universe(&self) -> Universe {
+        let ((min_x, min_y), _) = self.bounds().unwrap();
+        let mut universe = Universe::new();
+        for (x, y) in self.cells().alive() {
+            universe.set(min_x + x as i64, min_y + y as i64, CellState::Alive);
+        }
+        universe
+    }
+
+    fn set_universe(&mut self, universe: &Universe) {
+        let cells = self.cells_mut();
+        *cells = Cells::new(cells.width(), cells.height());
+        for (x, y) in universe.alive() {
+            Engine::set(self, x, y, CellState::Alive);
+        }
+    }
+
+    fn chunks_in(&self, range: ChunkRange) -> Vec<((i64, i64), Cells)> {
+        self.universe().chunks_in(range)
+    }
+
+    fn bounds(&self) -> Option<((i64, i64), (i64, i64))> {
+        let (width, height) = (self.cells().width() as i64, self.cells().height() as i64);
+        let (min_x, min_y) = (-(width / 2), -(height / 2));
+        Some(((min_x, min_y), (min_x + width - 1, min_y + height - 1)))
+    }
+
+    fn step(&mut self) {
+        Life::step(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::life::{Topology, NAMED_RULES};
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Universe with the cells of `rows` alive where they are `#`, the first
+    /// row at `y = origin.1` and the first column at `x = origin.0`.
+    fn universe(origin: (i64, i64), rows: &[&str]) -> Universe {
+        let mut universe = Universe::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == '#' {
+                    universe.set(origin.0 + x as i64, origin.1 + y as i64, CellState::Alive);
+                }
+            }
+        }
+        universe
+    }
+
+    #[test]
+    fn cells_anywhere() {
+        assert_eq!(locate(0, 15), ((0, 0), (0, 15)));
+        assert_eq!(locate(16, -1), ((1, -1), (0, 15)));
+        assert_eq!(locate(-17, -16), ((-2, -1), (15, 0)));
+
+        let mut universe = Universe::new();
+        for (x, y) in [(-1, -1), (0, 0), (1_000_000, -3), (15, 15)] {
+            universe.set(x, y, CellState::Alive);
+            assert_eq!(universe.get(x, y), CellState::Alive);
+        }
+        assert_eq!(universe.get(-1, 0), CellState::Dead);
+        assert_eq!(universe.population(), 4);
+        assert_eq!(universe.chunks().count(), 3);
+        assert_eq!(universe.bounds(), Some(((-1, -3), (1_000_000, 15))));
+
+        // Chunks go once their last cell dies
+        universe.set(1_000_000, -3, CellState::Dead);
+        universe.set(1_000_001, -3, CellState::Dead);
+        assert_eq!(universe.chunks().count(), 2);
+        assert_eq!(universe.bounds(), Some(((-1, -1), (15, 15))));
+
+        universe.clear();
+        assert_eq!(universe, Universe::new());
+        assert_eq!(universe.bounds(), None);
+    }
+
+    #[test]
+    fn agrees_with_the_bounded_grid() {
+        // The R-pentomino keeps changing for over a thousand generations
+        let r_pentomino = universe((46, 46), &[".##", "##.", ".#."]);
+        let mut cells = Cells::new(96, 96);
+        for (x, y) in r_pentomino.alive() {
+            cells.set(x as usize, y as usize, CellState::Alive);
+        }
+        let mut bounded = Life::new(Rule::conway(), cells);
+        let mut sparse = SparseLife::new(Rule::conway(), r_pentomino);
+
+        // Long enough to cross chunks, short enough to stay off the grid edges
+        for generation in 1..=100 {
+            bounded.step();
+            sparse.step();
+
+            let mut alive = sparse.universe().alive().collect::<Vec<_>>();
+            alive.sort_by_key(|(x, y)| (*y, *x));
+            let expected = bounded
+                .cells()
+                .alive()
+                .map(|(x, y)| (x as i64, y as i64))
+                .collect::<Vec<_>>();
+            assert_eq!(alive, expected, "generation {}", generation);
+        }
+        assert_eq!(sparse.generation(), 100);
+    }
+
+    #[test]
+    fn gliders_fly_on_forever() {
+        let glider = ["###", "#..", ".#."];
+        let mut life = SparseLife::new(Rule::conway(), universe((0, 0), &glider));
+
+        // One cell diagonally every 4 generations, across many chunks
+        for _ in 0..400 {
+            life.step();
+        }
+        assert_eq!(life.universe(), universe((-100, -100), &glider));
+        assert!(life.universe().chunks().count() <= 4);
+    }
+
+    #[test]
+    fn larger_than_life_agrees_with_the_bounded_grid() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut soup = Universe::new();
+        for y in 40..70 {
+            for x in 40..70 {
+                if rng.gen_bool(0.5) {
+                    soup.set(x, y, CellState::Alive);
+                }
+            }
+        }
+        let mut cells = Cells::new(110, 110);
+        for (x, y) in soup.alive() {
+            cells.set(x as usize, y as usize, CellState::Alive);
+        }
+
+        // Bosco's rule, counting over 120 cells up to 5 away
+        let rule: Rule = NAMED_RULES[4].1.parse().unwrap();
+        let mut bounded = Life::new(rule, cells);
+        let mut sparse = SparseLife::new(rule, soup);
+        for generation in 1..=20 {
+            bounded.step();
+            sparse.step();
+
+            let mut alive = sparse.universe().alive().collect::<Vec<_>>();
+            alive.sort_by_key(|(x, y)| (*y, *x));
+            let expected = bounded
+                .cells()
+                .alive()
+                .map(|(x, y)| (x as i64, y as i64))
+                .collect::<Vec<_>>();
+            assert_eq!(alive, expected, "generation {}", generation);
+        }
+        // Clear of the grid edges, where the engines would differ
+        let ((min_x, min_y), (max_x, max_y)) = sparse.universe().bounds().unwrap();
+        assert!(min_x.min(min_y) > 5 && max_x.max(max_y) < 105);
+    }
+
+    #[test]
+    fn grids_run_as_engines() {
+        let mut life = Life::new(Rule::conway(), Cells::new(10, 6));
+        life.topology = Topology::Torus;
+        let engine: &mut dyn Engine = &mut life;
+        assert_eq!(engine.bounds(), Some(((-5, -3), (4, 2))));
+
+        // Centred on the origin, nothing lives past the edges
+        engine.set(-5, -3, CellState::Alive);
+        engine.set(4, 2, CellState::Alive);
+        engine.set(5, 0, CellState::Alive);
+        assert_eq!(engine.get(4, 2), CellState::Alive);
+        assert_eq!(engine.get(5, 0), CellState::Dead);
+        assert_eq!(engine.population(), 2);
+        assert_eq!(life.cells().get(0, 0), CellState::Alive);
+        assert_eq!(life.cells().get(9, 5), CellState::Alive);
+
+        // A blinker in the bottom corner, across the edges of the torus
+        let blinker = universe((-5, -3), &["##"]);
+        let mut blinker_and_beyond = blinker.clone();
+        blinker_and_beyond.set(4, -3, CellState::Alive);
+        blinker_and_beyond.set(-6, -3, CellState::Alive);
+        life.set_universe(&blinker_and_beyond);
+        assert_eq!(Engine::population(&life), 3);
+
+        Engine::step(&mut life);
+        let mut standing = universe((-5, -3), &["#", "#"]);
+        standing.set(-5, 2, CellState::Alive);
+        assert_eq!(Engine::universe(&life), standing);
+        Engine::step(&mut life);
+        let mut lying = blinker;
+        lying.set(4, -3, CellState::Alive);
+        assert_eq!(Engine::universe(&life), lying);
+    }
+}
@@ -0,0 +1,310 @@
+//! What the camera shows of the universe. Every chunk with live cells in view
+//! is drawn as one small texture, the camera pans with the arrow keys or by
+//! dragging with the middle mouse button and zooms with the mouse wheel.
+
+use super::*;
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::render::{
+    render_resource::{Extent3d, TextureDimension, TextureFormat},
+    texture::ImageSampler,
+};
+use std::collections::HashMap;
+use universe::{ChunkRange, CHUNK_SIZE};
+
+/// Side of a cell in world units, which are pixels when not zoomed.
+pub const CELL_SIZE: f32 = 16.0;
+
+/// Closest and furthest zoom, in world units per pixel.
+const MIN_SCALE: f32 = 1.0 / 8.0;
+const MAX_SCALE: f32 = 32.0;
+/// Zoom change for one line of the mouse wheel.
+const ZOOM_STEP: f32 = 1.2;
+/// Panning with the arrow keys, in pixels per second.
+const PAN_SPEED: f32 = 600.0;
+
+/// World position under `cursor`, measured in pixels from the bottom left
+/// corner of a window of `window_size`.
+pub fn screen_to_world(cursor: Vec2, window_size: Vec2, camera: &Transform, scale: f32) -> Vec2 {
+    camera.translation.truncate() + (cursor - window_size / 2.0) * scale
+}
+
+pub fn world_to_cell(position: Vec2) -> GridCoordinate {
+    let cell = (position / CELL_SIZE).floor();
+    GridCoordinate {
+        x: cell.x as i64,
+        y: cell.y as i64,
+    }
+}
+
+/// Cell in the middle of the view.
+pub fn view_center(camera: &Transform) -> GridCoordinate {
+    world_to_cell(camera.translation.truncate())
+}
+
+/// Cell under the mouse cursor.
+pub fn cursor_cell(
+    windows: &Windows,
+    camera: &Transform,
+    projection: &OrthographicProjection,
+) -> Option<GridCoordinate> {
+    let window = windows.get_primary()?;
+    let window_size = Vec2::new(window.width(), window.height());
+    let cursor = window.cursor_position()?;
+    Some(world_to_cell(screen_to_world(
+        cursor,
+        window_size,
+        camera,
+        projection.scale,
+    )))
+}
+
+/// Chunks the camera sees, at least in part.
+pub fn visible_chunks(window_size: Vec2, camera: &Transform, scale: f32) -> ChunkRange {
+    let chunk = |corner: Vec2| {
+        let cell = world_to_cell(screen_to_world(corner, window_size, camera, scale));
+        universe::locate(cell.x, cell.y).0
+    };
+    (chunk(Vec2::ZERO), chunk(window_size))
+}
+
+pub fn camera_system(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    mut dragged_from: Local<Option<Vec2>>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+) {
+    let (mut camera, mut projection) = match cameras.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    let cursor = window.cursor_position();
+
+    // Panning, in pixels
+    let mut pan = Vec2::ZERO;
+    for (key, direction) in [
+        (KeyCode::Left, Vec2::NEG_X),
+        (KeyCode::Right, Vec2::X),
+        (KeyCode::Down, Vec2::NEG_Y),
+        (KeyCode::Up, Vec2::Y),
+    ] {
+        if keys.pressed(key) {
+            pan += direction * PAN_SPEED * time.delta_seconds();
+        }
+    }
+    if mouse_buttons.pressed(MouseButton::Middle) {
+        if let (Some(from), Some(cursor)) = (*dragged_from, cursor) {
+            pan += from - cursor;
+        }
+        *dragged_from = cursor;
+    } else {
+        *dragged_from = None;
+    }
+    camera.translation += (pan * projection.scale).extend(0.0);
+
+    // Zooming, keeping what is under the cursor in place
+    let lines = mouse_wheel
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 40.0,
+        })
+        .sum::<f32>();
+    if lines != 0.0 {
+        let anchor = cursor.unwrap_or(window_size / 2.0);
+        let before = screen_to_world(anchor, window_size, &camera, projection.scale);
+        projection.scale = (projection.scale * ZOOM_STEP.powf(-lines)).clamp(MIN_SCALE, MAX_SCALE);
+        let after = screen_to_world(anchor, window_size, &camera, projection.scale);
+        camera.translation += (before - after).extend(0.0);
+    }
+}
+
+/// Sprites of the chunks in view, and the textures they show.
+#[derive(Resource, Default)]
+pub struct ChunkSprites(HashMap<(i64, i64), (Entity, Handle<Image>)>);
+
+/// Pixels of a chunk, its live cells in `color` and the dead ones transparent,
+/// the top row first.
+fn chunk_pixels(cells: &Cells, color: Color) -> Vec<u8> {
+    let alive = color
+        .as_rgba_f32()
+        .map(|channel| (channel * 255.0).round() as u8);
+    (0..CHUNK_SIZE)
+        .rev()
+        .flat_map(|y| (0..CHUNK_SIZE).map(move |x| (x, y)))
+        .flat_map(|(x, y)| match cells.get(x as isize, y as isize) {
+            CellState::Alive => alive,
+            CellState::Dead => [0; 4],
+        })
+        .collect()
+}
+
+fn chunk_image(pixels: Vec<u8>) -> Image {
+    let size = Extent3d {
+        width: CHUNK_SIZE as u32,
+        height: CHUNK_SIZE as u32,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image::new_fill(
+        size,
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    image.data = pixels;
+    // Sharp cells rather than blurry ones when zoomed in
+    image.sampler_descriptor = ImageSampler::nearest();
+    image
+}
+
+/// Background of a world with edges, from its lowest to its highest cell.
+pub fn world_sprite(
+    ((min_x, min_y), (max_x, max_y)): ((i64, i64), (i64, i64)),
+    color: Color,
+) -> SpriteBundle {
+    let min = Vec2::new(min_x as f32, min_y as f32) * CELL_SIZE;
+    let max = Vec2::new(max_x as f32 + 1.0, max_y as f32 + 1.0) * CELL_SIZE;
+    SpriteBundle {
+        sprite: Sprite {
+            color,
+            custom_size: Some(max - min),
+            ..Default::default()
+        },
+        transform: Transform::from_translation(((min + max) / 2.0).extend(0.0)),
+        ..Default::default()
+    }
+}
+
+/// Spawns and despawns chunk sprites as chunks come into view or fill up, and
+/// redraws them when the game changed. Dead cells are transparent.
+#[allow(clippy::too_many_arguments)]
+pub fn chunk_sprites_system(
+    mut commands: Commands,
+    game: Res<Game>,
+    config: Res<GameOfLifeConfig>,
+    windows: Res<Windows>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+    mut last_visible: Local<Option<ChunkRange>>,
+    mut sprites: ResMut<ChunkSprites>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let (camera, projection) = match cameras.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let visible = visible_chunks(
+        Vec2::new(window.width(), window.height()),
+        camera,
+        projection.scale,
+    );
+    if !game.is_changed() && *last_visible == Some(visible) {
+        return;
+    }
+    *last_visible = Some(visible);
+
+    let chunks = game
+        .chunks_in(visible)
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    sprites.0.retain(|chunk, (entity, _)| {
+        let keep = chunks.contains_key(chunk);
+        if !keep {
+            // Dropping the handle frees the texture
+            commands.entity(*entity).despawn();
+        }
+        keep
+    });
+
+    let chunk_side = CHUNK_SIZE as f32 * CELL_SIZE;
+    for (&chunk, cells) in &chunks {
+        match sprites.0.get(&chunk) {
+            Some((_, image)) => {
+                if game.is_changed() {
+                    if let Some(image) = images.get_mut(image) {
+                        image.data = chunk_pixels(cells, config.alive_color);
+                    }
+                }
+            }
+            None => {
+                let image = images.add(chunk_image(chunk_pixels(cells, config.alive_color)));
+                let center = (Vec2::new(chunk.0 as f32, chunk.1 as f32) + 0.5) * chunk_side;
+                let entity = commands
+                    .spawn(SpriteBundle {
+                        sprite: Sprite {
+                            custom_size: Some(Vec2::splat(chunk_side)),
+                            ..Default::default()
+                        },
+                        texture: image.clone(),
+                        // In front of the world
+                        transform: Transform::from_translation(center.extend(1.0)),
+                        ..Default::default()
+                    })
+                    .id();
+                sprites.0.insert(chunk, (entity, image));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_to_cells_and_chunks() {
+        let window_size = Vec2::new(800.0, 600.0);
+        let camera = Transform::from_xyz(-8.0, 40.0, 0.0);
+
+        let cell = |x, y, scale| {
+            let cell = world_to_cell(screen_to_world(
+                Vec2::new(x, y),
+                window_size,
+                &camera,
+                scale,
+            ));
+            (cell.x, cell.y)
+        };
+        // The middle of the window is where the camera is
+        assert_eq!(cell(400.0, 300.0, 1.0), (-1, 2));
+        assert_eq!(cell(408.0, 300.0, 1.0), (0, 2));
+        assert_eq!(cell(0.0, 0.0, 1.0), (-26, -17));
+        // Zoomed out twice as far
+        assert_eq!(cell(0.0, 0.0, 2.0), (-51, -35));
+
+        assert_eq!(
+            visible_chunks(window_size, &camera, 1.0),
+            ((-2, -2), (1, 1))
+        );
+        assert_eq!(view_center(&camera).x, -1);
+    }
+
+    #[test]
+    fn chunks_draw_top_row_first() {
+        let mut cells = Cells::new(CHUNK_SIZE, CHUNK_SIZE);
+        cells.set(1, CHUNK_SIZE - 1, CellState::Alive);
+        cells.set(0, 0, CellState::Alive);
+
+        let pixels = chunk_pixels(&cells, Color::rgb(1.0, 0.0, 0.5));
+        assert_eq!(pixels.len(), CHUNK_SIZE * CHUNK_SIZE * 4);
+        assert_eq!(&pixels[4..8], &[255, 0, 128, 255]);
+        let last_row = (CHUNK_SIZE - 1) * CHUNK_SIZE * 4;
+        assert_eq!(&pixels[last_row..last_row + 4], &[255, 0, 128, 255]);
+        assert_eq!(
+            pixels.iter().filter(|channel| **channel == 255).count(),
+            2 * 2
+        );
+    }
+}